@@ -1,15 +1,59 @@
-use crate::compression;
+use crate::chksums;
+use crate::compression::{self, Limits};
 use crate::errors::*;
 use apt_parser::release::ReleaseHash;
+use std::borrow::Cow;
 use std::str;
 
+/// The suffixes an index (like `Sources` or `Packages`) may be listed with in a release file
+pub const INDEX_VARIANTS: &[&str] = &["", ".gz", ".xz"];
+
+/// The compression suffixes an upstream source tarball (`.orig.tar.*`) may have
+pub const ORIG_TARBALL_SUFFIXES: &[&str] = &[
+    ".orig.tar.gz",
+    ".orig.tar.xz",
+    ".orig.tar.bz2",
+    ".orig.tar.lzma",
+    ".orig.tar.zst",
+    ".orig.tar.lz",
+];
+
+pub fn is_orig_tarball(filename: &str) -> bool {
+    ORIG_TARBALL_SUFFIXES
+        .iter()
+        .any(|suffix| filename.ends_with(suffix))
+}
+
+/// Split an `Acquire-By-Hash` path like `main/source/by-hash/SHA256/<hash>` into directory and hash
+pub fn split_by_hash_path(path: &str) -> Option<(&str, &str)> {
+    path.rsplit_once("/by-hash/SHA256/")
+}
+
+/// Determine the `Acquire-By-Hash` path of a file listed in a release file
+pub fn by_hash_path(filename: &str, sha256: &str) -> String {
+    match filename.rsplit_once('/') {
+        Some((dir, _)) => format!("{dir}/by-hash/SHA256/{sha256}"),
+        None => format!("by-hash/SHA256/{sha256}"),
+    }
+}
+
+/// Remove a compression suffix from an index path, e.g. `main/source/Sources.xz`
+pub fn strip_index_variant(path: &str) -> &str {
+    INDEX_VARIANTS
+        .iter()
+        .filter(|suffix| !suffix.is_empty())
+        .find_map(|suffix| path.strip_suffix(suffix))
+        .unwrap_or(path)
+}
+
 pub struct SourcesIndex {
     pkgs: Vec<SourcePkg>,
 }
 
 impl SourcesIndex {
-    pub fn parse(bytes: &[u8]) -> Result<Self> {
-        let buf = compression::decompress(bytes).context("Failed to decompress sources index")?;
+    pub fn parse(bytes: &[u8], limits: &Limits) -> Result<Self> {
+        let buf =
+            compression::decompress(bytes, limits).context("Failed to decompress sources index")?;
         let sources = str::from_utf8(&buf)?;
 
         let mut pkgs = Vec::new();
@@ -27,6 +71,11 @@ impl SourcesIndex {
                     continue;
                 };
                 package.version = Some(value.to_string());
+            } else if let Some(value) = line.strip_prefix("Directory: ") {
+                let Some(package) = package.as_mut() else {
+                    continue;
+                };
+                package.directory = Some(value.to_string());
             } else if line.is_empty() {
                 if let Some(package) = package.take() {
                     pkgs.push(package);
@@ -89,13 +138,7 @@ impl SourcesIndex {
                 }
             }
 
-            for chksum in &pkg.checksums_sha256 {
-                if !chksum.filename.ends_with(".orig.tar.gz")
-                    && !chksum.filename.ends_with(".orig.tar.xz")
-                {
-                    continue;
-                }
-
+            for chksum in pkg.orig_tarballs() {
                 if chksum.hash == sha256 {
                     info!("File verified successfully");
                     return Ok(pkg);
@@ -105,15 +148,41 @@ impl SourcesIndex {
 
         bail!("Could not find source tarball with matching hash in source index")
     }
+
+    pub fn find_pkg(&self, name: &str, version: Option<&str>) -> Result<&SourcePkg> {
+        let mut matches = self.pkgs.iter().filter(|pkg| {
+            pkg.package == name
+                && version.is_none_or(|version| pkg.version.as_deref() == Some(version))
+        });
+
+        let pkg = matches.next().with_context(|| {
+            anyhow!("Could not find package {name:?} (version={version:?}) in source index")
+        })?;
+        if matches.next().is_some() {
+            bail!("Found multiple versions of {name:?} in source index, please specify a version");
+        }
+
+        Ok(pkg)
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct SourcePkg {
     pub package: String,
     pub version: Option<String>,
+    pub directory: Option<String>,
     pub checksums_sha256: Vec<ReleaseHash>,
 }
 
+impl SourcePkg {
+    /// The entries for the upstream source tarball (`.orig.tar.*`)
+    pub fn orig_tarballs(&self) -> impl Iterator<Item = &ReleaseHash> {
+        self.checksums_sha256
+            .iter()
+            .filter(|chksum| is_orig_tarball(&chksum.filename))
+    }
+}
+
 pub struct Release {
     release: apt_parser::Release,
 }
@@ -125,26 +194,203 @@ impl Release {
         Ok(Release { release })
     }
 
-    pub fn find_source_entry_by_sha256(&self, sha256: &str) -> Result<&ReleaseHash> {
+    pub fn sha256sums(&self) -> Result<&[ReleaseHash]> {
         let sha256sums = self
             .release
             .sha256sum
             .as_ref()
             .context("Release file has no sha256sum section")?;
+        Ok(sha256sums)
+    }
 
-        let sources_entry = sha256sums
+    /// Find the entry for a path, `Acquire-By-Hash` paths are resolved to their canonical entry
+    pub fn find_entry_by_path(&self, path: &str) -> Result<&ReleaseHash> {
+        let by_hash = split_by_hash_path(path);
+
+        let entry = self
+            .sha256sums()?
             .iter()
-            .filter(|entry| entry.filename.contains("/source/Sources"))
             .find(|entry| {
-                debug!("Found sha256sum entry for sources index: {entry:?}");
-                entry.hash == sha256
+                trace!("Found sha256sum entry in release file: {entry:?}");
+                if let Some((dir, hash)) = by_hash {
+                    let parent = entry.filename.rsplit_once('/').map(|(parent, _)| parent);
+                    parent == Some(dir) && entry.hash == hash
+                } else {
+                    entry.filename == path
+                }
             })
-            .with_context(|| {
-                anyhow!(
-                    "Failed to find matching source entry in release file with sha256={sha256:?}"
-                )
-            })?;
+            .with_context(|| anyhow!("Failed to find entry in release file with path={path:?}"))?;
+        debug!("Found sha256sum entry for {path:?}: {entry:?}");
+
+        Ok(entry)
+    }
+
+    pub fn find_source_entry(&self, path: &str, sha256: &str, size: u64) -> Result<&ReleaseHash> {
+        let entry = self.find_entry_by_path(path)?;
+
+        if entry.hash != sha256 {
+            bail!(
+                "Release file entry for {path:?} has sha256={:?}, expected sha256={sha256:?}",
+                entry.hash
+            );
+        }
+
+        if entry.size != size {
+            bail!(
+                "Release file entry for {path:?} has size={}, expected size={size}",
+                entry.size
+            );
+        }
+
+        Ok(entry)
+    }
+
+    /// Find a release file entry for a sources index, regardless of which compression variant is provided
+    ///
+    /// The file is first compared with all variants listed for `path` as-is, if none of them match
+    /// the decompressed content is compared with the uncompressed entry. The path may also be an
+    /// `Acquire-By-Hash` path.
+    pub fn find_sources_entry(
+        &self,
+        path: &str,
+        bytes: &[u8],
+        limits: &Limits,
+    ) -> Result<&ReleaseHash> {
+        let path = if split_by_hash_path(path).is_some() {
+            let entry = self.find_entry_by_path(path)?;
+            debug!("Resolved by-hash path {path:?} to {:?}", entry.filename);
+            entry.filename.as_str()
+        } else {
+            path
+        };
+        let path = strip_index_variant(path);
+
+        let sha256 = chksums::sha256(bytes);
+        for suffix in INDEX_VARIANTS {
+            let variant = format!("{path}{suffix}");
+            match self.find_source_entry(&variant, &sha256, bytes.len() as u64) {
+                Ok(entry) => {
+                    info!("Sources index matches release file entry {variant:?}");
+                    return Ok(entry);
+                }
+                Err(err) => debug!("Sources index does not match {variant:?}: {err:#}"),
+            }
+        }
+
+        let decompressed =
+            compression::decompress(bytes, limits).context("Failed to decompress sources index")?;
+        let Cow::Owned(decompressed) = decompressed else {
+            bail!("Failed to find release file entry matching sources index for {path:?}");
+        };
+
+        debug!("Comparing decompressed sources index with {path:?}");
+        let sha256 = chksums::sha256(&decompressed);
+        let entry = self
+            .find_source_entry(path, &sha256, decompressed.len() as u64)
+            .context("Decompressed sources index does not match uncompressed release file entry")?;
+        info!("Decompressed sources index matches release file entry {path:?}");
+
+        Ok(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sources_find_pkg() {
+        let sources = b"Package: cmatrix
+Binary: cmatrix, cmatrix-xfont
+Version: 2.0-6
+Directory: pool/main/c/cmatrix
+Checksums-Sha256:
+ 1cb1b9a5b7e4ba2d3a4b8c44ae5b1c41f6ff17e7b59b3a7c1b8ab0cd3dba3b2d 2071 cmatrix_2.0-6.dsc
+ ad93ba39acd383696ab6a9ebbed1259ecf2d3cf9f49d6b97038c66f80749e99a 74376 cmatrix_2.0.orig.tar.gz
+ f17d1e7cf44a0f5e1e7e3d4f1e1e3b2e1dbb3c0be1b5e3cb1c1a3bb2b8c8d1e4 8728 cmatrix_2.0-6.debian.tar.xz
+Format: 3.0 (quilt)
+
+";
+        let sources = SourcesIndex::parse(sources, &Limits::DEFAULT).unwrap();
+        let pkg = sources.find_pkg("cmatrix", None).unwrap();
+        assert_eq!(pkg.version.as_deref(), Some("2.0-6"));
+        assert_eq!(pkg.directory.as_deref(), Some("pool/main/c/cmatrix"));
+        assert_eq!(pkg.checksums_sha256.len(), 3);
+
+        let origs = pkg
+            .orig_tarballs()
+            .map(|entry| entry.filename.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(origs, &["cmatrix_2.0.orig.tar.gz"]);
+
+        assert!(sources.find_pkg("cmatrix", Some("2.0-5")).is_err());
+        assert!(sources.find_pkg("vim", None).is_err());
+    }
+
+    #[test]
+    fn test_find_orig_tarball_bz2() {
+        let sources = b"Package: foo
+Version: 1.0-1
+Checksums-Sha256:
+ 1cb1b9a5b7e4ba2d3a4b8c44ae5b1c41f6ff17e7b59b3a7c1b8ab0cd3dba3b2d 1337 foo_1.0-1.dsc
+ ad93ba39acd383696ab6a9ebbed1259ecf2d3cf9f49d6b97038c66f80749e99a 74376 foo_1.0.orig.tar.bz2
+ f17d1e7cf44a0f5e1e7e3d4f1e1e3b2e1dbb3c0be1b5e3cb1c1a3bb2b8c8d1e4 8728 foo_1.0-1.debian.tar.bz2
+
+";
+        let sources = SourcesIndex::parse(sources, &Limits::DEFAULT).unwrap();
+        let pkg = sources
+            .find_pkg_by_sha256(
+                None,
+                None,
+                "ad93ba39acd383696ab6a9ebbed1259ecf2d3cf9f49d6b97038c66f80749e99a",
+            )
+            .unwrap();
+        assert_eq!(pkg.package, "foo");
+        let origs = pkg
+            .orig_tarballs()
+            .map(|entry| entry.filename.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(origs, &["foo_1.0.orig.tar.bz2"]);
+
+        assert!(is_orig_tarball("foo_1.0.orig.tar.lzma"));
+        assert!(is_orig_tarball("foo_1.0.orig.tar.zst"));
+        assert!(is_orig_tarball("foo_1.0.orig.tar.lz"));
+        assert!(!is_orig_tarball("foo_1.0-1.debian.tar.bz2"));
+    }
+
+    #[test]
+    fn test_by_hash_path() {
+        let path = by_hash_path(
+            "main/source/Sources.xz",
+            "ba14ca35563ace9dc1e81446f6d72979cdc5aa7ea5c558cb0fe5071736c602b2",
+        );
+        assert_eq!(
+            path,
+            "main/source/by-hash/SHA256/ba14ca35563ace9dc1e81446f6d72979cdc5aa7ea5c558cb0fe5071736c602b2"
+        );
+        assert_eq!(
+            split_by_hash_path(&path),
+            Some((
+                "main/source",
+                "ba14ca35563ace9dc1e81446f6d72979cdc5aa7ea5c558cb0fe5071736c602b2"
+            ))
+        );
+        assert_eq!(split_by_hash_path("main/source/Sources.xz"), None);
+    }
 
-        Ok(sources_entry)
+    #[test]
+    fn test_strip_index_variant() {
+        assert_eq!(
+            strip_index_variant("main/source/Sources.xz"),
+            "main/source/Sources"
+        );
+        assert_eq!(
+            strip_index_variant("main/source/Sources.gz"),
+            "main/source/Sources"
+        );
+        assert_eq!(
+            strip_index_variant("main/source/Sources"),
+            "main/source/Sources"
+        );
     }
 }
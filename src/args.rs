@@ -1,3 +1,4 @@
+use crate::compression::Limits;
 use crate::errors::*;
 use crate::plumbing;
 use clap::{ArgAction, CommandFactory, Parser, Subcommand};
@@ -14,10 +15,40 @@ pub struct Args {
     /// Reduce logging output (can be used multiple times)
     #[arg(short, long, global = true, action(ArgAction::Count))]
     pub quiet: u8,
+    #[command(flatten)]
+    pub limits: LimitArgs,
     #[command(subcommand)]
     pub subcommand: SubCommand,
 }
 
+/// Resource limits for processing compressed files from untrusted sources
+#[derive(Debug, clap::Args)]
+pub struct LimitArgs {
+    /// Maximum number of bytes a file may decompress to
+    #[arg(long, global = true, default_value_t = Limits::DEFAULT.max_size)]
+    pub max_decompressed_size: u64,
+    /// Maximum ratio of decompressed size to compressed size
+    #[arg(long, global = true, default_value_t = Limits::DEFAULT.max_ratio)]
+    pub max_compression_ratio: u64,
+    /// Maximum number of entries read from a tar archive
+    #[arg(long, global = true, default_value_t = Limits::DEFAULT.max_entries)]
+    pub max_tar_entries: u64,
+    /// Maximum number of bytes decompressed into memory, like indexes and metadata files
+    #[arg(long, global = true, default_value_t = Limits::DEFAULT.max_memory)]
+    pub max_memory_size: u64,
+}
+
+impl From<&LimitArgs> for Limits {
+    fn from(args: &LimitArgs) -> Self {
+        Limits {
+            max_size: args.max_decompressed_size,
+            max_ratio: args.max_compression_ratio,
+            max_entries: args.max_tar_entries,
+            max_memory: args.max_memory_size,
+        }
+    }
+}
+
 #[derive(Debug, Subcommand)]
 pub enum SubCommand {
     /*
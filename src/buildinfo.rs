@@ -1,33 +1,144 @@
+use crate::compression::{self, Limits};
 use crate::errors::*;
+use crate::pkginfo::{self, ArchLinuxPkginfo};
 use bstr::ByteSlice;
-use ruzstd::decoding::StreamingDecoder;
 use std::io::Read;
 use std::str;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Default, PartialEq)]
 pub struct ArchLinuxBuildinfo {
+    pub format: u8,
+    pub pkgname: String,
+    pub pkgbase: String,
+    /// The full version of the package, including epoch and pkgrel
+    pub pkgver: String,
+    pub pkgarch: String,
     pub pkgbuild_sha256sum: String,
+    pub packager: String,
+    pub builddate: u64,
+    pub builddir: String,
+    /// Only present since format version 2
+    pub startdir: Option<String>,
+    /// Only present since format version 2
+    pub buildtool: Option<String>,
+    /// Only present since format version 2
+    pub buildtoolver: Option<String>,
+    pub buildenv: Vec<String>,
+    pub options: Vec<String>,
+    pub installed: Vec<String>,
+}
+
+/// Assign a key that may only be listed once, like in .BUILDINFO and .PKGINFO files
+pub(crate) fn set_once(field: &mut Option<String>, key: &str, value: &str) -> Result<()> {
+    if field.is_some() {
+        bail!("Duplicate key: {key:?}");
+    }
+    *field = Some(value.to_string());
+    Ok(())
+}
+
+fn required(field: Option<String>, key: &str) -> Result<String> {
+    field.with_context(|| anyhow!("Failed to find {key} in .BUILDINFO"))
 }
 
 pub fn parse_archlinux(bytes: &[u8]) -> Result<ArchLinuxBuildinfo> {
+    let mut format = None;
+    let mut pkgname = None;
+    let mut pkgbase = None;
+    let mut pkgver = None;
+    let mut pkgarch = None;
+    let mut pkgbuild_sha256sum = None;
+    let mut packager = None;
+    let mut builddate = None;
+    let mut builddir = None;
+    let mut startdir = None;
+    let mut buildtool = None;
+    let mut buildtoolver = None;
+    let mut buildenv = Vec::new();
+    let mut options = Vec::new();
+    let mut installed = Vec::new();
+
     for line in bytes.lines() {
         trace!("Line in .BUILDINFO: {:?}", bstr::BStr::new(line));
         let line = str::from_utf8(line)?;
 
-        if let Some(hash) = line.strip_prefix("pkgbuild_sha256sum = ") {
-            return Ok(ArchLinuxBuildinfo {
-                pkgbuild_sha256sum: hash.to_string(),
-            });
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once(" = ")
+            .with_context(|| anyhow!("Malformed line in .BUILDINFO: {line:?}"))?;
+
+        match key {
+            "format" => set_once(&mut format, key, value)?,
+            "pkgname" => set_once(&mut pkgname, key, value)?,
+            "pkgbase" => set_once(&mut pkgbase, key, value)?,
+            "pkgver" => set_once(&mut pkgver, key, value)?,
+            "pkgarch" => set_once(&mut pkgarch, key, value)?,
+            "pkgbuild_sha256sum" => set_once(&mut pkgbuild_sha256sum, key, value)?,
+            "packager" => set_once(&mut packager, key, value)?,
+            "builddate" => set_once(&mut builddate, key, value)?,
+            "builddir" => set_once(&mut builddir, key, value)?,
+            "startdir" => set_once(&mut startdir, key, value)?,
+            "buildtool" => set_once(&mut buildtool, key, value)?,
+            "buildtoolver" => set_once(&mut buildtoolver, key, value)?,
+            "buildenv" => buildenv.push(value.to_string()),
+            "options" => options.push(value.to_string()),
+            "installed" => installed.push(value.to_string()),
+            _ => warn!("Unknown key in .BUILDINFO: {key:?}"),
+        }
+    }
+
+    let format = required(format, "format")?;
+    let format = match format.as_str() {
+        "1" => 1,
+        "2" => 2,
+        _ => bail!("Unsupported .BUILDINFO format version: {format:?}"),
+    };
+
+    if format >= 2 {
+        for (field, key) in [
+            (&startdir, "startdir"),
+            (&buildtool, "buildtool"),
+            (&buildtoolver, "buildtoolver"),
+        ] {
+            if field.is_none() {
+                bail!("Failed to find {key} in .BUILDINFO (required since format version 2)");
+            }
         }
     }
-    bail!("Failed to find pkgbuild_sha256sum in .BUILDINFO")
+
+    let builddate = required(builddate, "builddate")?;
+    let builddate = builddate
+        .parse()
+        .with_context(|| anyhow!("Failed to parse builddate as number: {builddate:?}"))?;
+
+    Ok(ArchLinuxBuildinfo {
+        format,
+        pkgname: required(pkgname, "pkgname")?,
+        pkgbase: required(pkgbase, "pkgbase")?,
+        pkgver: required(pkgver, "pkgver")?,
+        pkgarch: required(pkgarch, "pkgarch")?,
+        pkgbuild_sha256sum: required(pkgbuild_sha256sum, "pkgbuild_sha256sum")?,
+        packager: required(packager, "packager")?,
+        builddate,
+        builddir: required(builddir, "builddir")?,
+        startdir,
+        buildtool,
+        buildtoolver,
+        buildenv,
+        options,
+        installed,
+    })
 }
 
-pub fn from_archlinux_pkg(bytes: &[u8]) -> Result<ArchLinuxBuildinfo> {
-    let decoder = StreamingDecoder::new(bytes)?;
+pub fn from_archlinux_pkg(bytes: &[u8], limits: &Limits) -> Result<ArchLinuxBuildinfo> {
+    let decoder = compression::decoder(bytes, limits)?;
     let mut tar = tar::Archive::new(decoder);
 
-    for entry in tar.entries()? {
+    for (num, entry) in (1..).zip(tar.entries()?) {
+        limits.check_entries(num)?;
         let mut entry = entry?;
         let path = entry.path()?;
         if path.to_str() != Some(".BUILDINFO") {
@@ -35,10 +146,245 @@ pub fn from_archlinux_pkg(bytes: &[u8]) -> Result<ArchLinuxBuildinfo> {
             continue;
         }
         debug!("Found .BUILDINFO file in package");
+        limits.check_memory(entry.size())?;
         let mut buf = Vec::new();
         entry.read_to_end(&mut buf)?;
-        return parse_archlinux(&buf);
+        return parse_archlinux(&buf).context("Failed to parse .BUILDINFO");
     }
 
     bail!("Failed to locate .BUILDINFO in package")
 }
+
+/// The metadata files embedded in an Arch Linux package
+#[derive(Debug, PartialEq)]
+pub struct ArchLinuxPkgMetadata {
+    pub buildinfo: ArchLinuxBuildinfo,
+    pub pkginfo: ArchLinuxPkginfo,
+}
+
+impl ArchLinuxPkgMetadata {
+    /// Make sure .PKGINFO and .BUILDINFO describe the same package
+    pub fn check_consistency(&self) -> Result<()> {
+        let (buildinfo, pkginfo) = (&self.buildinfo, &self.pkginfo);
+        for (key, pkginfo, buildinfo) in [
+            ("pkgname", &pkginfo.pkgname, &buildinfo.pkgname),
+            ("pkgver", &pkginfo.pkgver, &buildinfo.pkgver),
+            ("arch", &pkginfo.arch, &buildinfo.pkgarch),
+            ("packager", &pkginfo.packager, &buildinfo.packager),
+        ] {
+            if pkginfo != buildinfo {
+                bail!(".PKGINFO {key}={pkginfo:?} does not match .BUILDINFO {key}={buildinfo:?}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Make sure the email of the packager is listed in the user ids of the signing key
+    pub fn check_packager(&self, user_ids: &[String]) -> Result<()> {
+        let packager = &self.pkginfo.packager;
+        let email = pkginfo::packager_email(packager)
+            .with_context(|| anyhow!("Failed to find email in packager string: {packager:?}"))?;
+
+        if user_ids
+            .iter()
+            .filter_map(|uid| pkginfo::packager_email(uid))
+            .any(|uid| uid.eq_ignore_ascii_case(email))
+        {
+            Ok(())
+        } else {
+            bail!("Packager email {email:?} does not match any user id of the signing key: {user_ids:?}")
+        }
+    }
+}
+
+pub fn metadata_from_archlinux_pkg(bytes: &[u8], limits: &Limits) -> Result<ArchLinuxPkgMetadata> {
+    let decoder = compression::decoder(bytes, limits)?;
+    let mut tar = tar::Archive::new(decoder);
+
+    let mut buildinfo = None;
+    let mut pkginfo = None;
+    for (num, entry) in (1..).zip(tar.entries()?) {
+        limits.check_entries(num)?;
+        let mut entry = entry?;
+        let path = entry.path()?;
+        let target = match path.to_str() {
+            Some(".BUILDINFO") => &mut buildinfo,
+            Some(".PKGINFO") => &mut pkginfo,
+            _ => {
+                debug!("Skipping file in package: {path:?}");
+                continue;
+            }
+        };
+        debug!("Found {path:?} file in package");
+        if target.is_some() {
+            bail!("Found duplicate {path:?} in package");
+        }
+        limits.check_memory(entry.size())?;
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf)?;
+        *target = Some(buf);
+    }
+
+    let buildinfo = buildinfo.context("Failed to locate .BUILDINFO in package")?;
+    let pkginfo = pkginfo.context("Failed to locate .PKGINFO in package")?;
+    Ok(ArchLinuxPkgMetadata {
+        buildinfo: parse_archlinux(&buildinfo).context("Failed to parse .BUILDINFO")?,
+        pkginfo: pkginfo::parse_archlinux(&pkginfo).context("Failed to parse .PKGINFO")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    const BUILDINFO: &[u8] = b"format = 2
+pkgname = cmatrix
+pkgbase = cmatrix
+pkgver = 2.0-3
+pkgarch = x86_64
+pkgbuild_sha256sum = 6ed7af29ac762cca746c533046099708f78b0fe07b7bf6bc3a5e86df38c87180
+packager = kpcyrd <kpcyrd@archlinux.org>
+builddate = 1686262344
+builddir = /build
+startdir = /startdir
+buildtool = devtools
+buildtoolver = 1:1.0.1-1-any
+buildenv = !distcc
+buildenv = color
+buildenv = !ccache
+options = strip
+options = docs
+options = !libtool
+installed = acl-2.3.1-3-x86_64
+installed = ncurses-6.4_20230520-1-x86_64
+";
+
+    #[test]
+    fn test_parse_buildinfo() {
+        let buildinfo = parse_archlinux(BUILDINFO).unwrap();
+        assert_eq!(
+            buildinfo,
+            ArchLinuxBuildinfo {
+                format: 2,
+                pkgname: "cmatrix".to_string(),
+                pkgbase: "cmatrix".to_string(),
+                pkgver: "2.0-3".to_string(),
+                pkgarch: "x86_64".to_string(),
+                pkgbuild_sha256sum:
+                    "6ed7af29ac762cca746c533046099708f78b0fe07b7bf6bc3a5e86df38c87180".to_string(),
+                packager: "kpcyrd <kpcyrd@archlinux.org>".to_string(),
+                builddate: 1686262344,
+                builddir: "/build".to_string(),
+                startdir: Some("/startdir".to_string()),
+                buildtool: Some("devtools".to_string()),
+                buildtoolver: Some("1:1.0.1-1-any".to_string()),
+                buildenv: vec![
+                    "!distcc".to_string(),
+                    "color".to_string(),
+                    "!ccache".to_string(),
+                ],
+                options: vec![
+                    "strip".to_string(),
+                    "docs".to_string(),
+                    "!libtool".to_string(),
+                ],
+                installed: vec![
+                    "acl-2.3.1-3-x86_64".to_string(),
+                    "ncurses-6.4_20230520-1-x86_64".to_string(),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_buildinfo_format_validation() {
+        let v1 = BUILDINFO
+            .lines()
+            .filter(|line| {
+                !line.starts_with(b"startdir")
+                    && !line.starts_with(b"buildtool")
+                    && !line.starts_with(b"format")
+            })
+            .collect::<Vec<_>>()
+            .join(&b'\n');
+
+        let mut buildinfo = b"format = 1\n".to_vec();
+        buildinfo.extend(&v1);
+        let buildinfo = parse_archlinux(&buildinfo).unwrap();
+        assert_eq!(buildinfo.format, 1);
+        assert_eq!(buildinfo.startdir, None);
+
+        let mut buildinfo = b"format = 2\n".to_vec();
+        buildinfo.extend(&v1);
+        assert!(parse_archlinux(&buildinfo).is_err());
+
+        let mut buildinfo = b"format = 3\n".to_vec();
+        buildinfo.extend(&v1);
+        assert!(parse_archlinux(&buildinfo).is_err());
+    }
+
+    #[test]
+    fn test_metadata_consistency() {
+        let buildinfo = parse_archlinux(BUILDINFO).unwrap();
+        let pkginfo = pkginfo::parse_archlinux(
+            b"pkgname = cmatrix
+pkgver = 2.0-3
+packager = kpcyrd <kpcyrd@archlinux.org>
+size = 43573
+arch = x86_64
+",
+        )
+        .unwrap();
+        let mut metadata = ArchLinuxPkgMetadata { buildinfo, pkginfo };
+        metadata.check_consistency().unwrap();
+        metadata
+            .check_packager(&["kpcyrd <KPCYRD@archlinux.org>".to_string()])
+            .unwrap();
+        assert!(metadata
+            .check_packager(&["kpcyrd <git@rxv.cc>".to_string()])
+            .is_err());
+
+        metadata.pkginfo.pkgver = "2.0-4".to_string();
+        assert!(metadata.check_consistency().is_err());
+    }
+
+    #[test]
+    fn test_parse_buildinfo_duplicate_key() {
+        let mut buildinfo = BUILDINFO.to_vec();
+        buildinfo.extend(b"pkgbuild_sha256sum = 0000\n");
+        assert!(parse_archlinux(&buildinfo).is_err());
+    }
+
+    fn pkg_tar(buildinfo: &[u8]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(buildinfo.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, ".BUILDINFO", buildinfo)
+            .unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_from_archlinux_pkg_gzip() {
+        let mut encoder = libflate::gzip::Encoder::new(Vec::new()).unwrap();
+        io::copy(&mut &pkg_tar(BUILDINFO)[..], &mut encoder).unwrap();
+        let pkg = encoder.finish().into_result().unwrap();
+
+        let buildinfo = from_archlinux_pkg(&pkg, &Limits::DEFAULT).unwrap();
+        assert_eq!(buildinfo.pkgname, "cmatrix");
+    }
+
+    #[test]
+    fn test_from_archlinux_pkg_lz4() {
+        let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+        io::copy(&mut &pkg_tar(BUILDINFO)[..], &mut encoder).unwrap();
+        let pkg = encoder.finish().unwrap();
+
+        let buildinfo = from_archlinux_pkg(&pkg, &Limits::DEFAULT).unwrap();
+        assert_eq!(buildinfo.pkgname, "cmatrix");
+    }
+}
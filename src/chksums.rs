@@ -1,4 +1,26 @@
+use crate::errors::*;
 use digest::Digest;
+use std::io::{self, Read};
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+
+pub fn md5(bytes: &[u8]) -> String {
+    let mut hasher = md5::Md5::new();
+    hasher.update(bytes);
+    hex::encode(hasher.finalize())
+}
+
+pub fn sha1(bytes: &[u8]) -> String {
+    let mut hasher = sha1::Sha1::new();
+    hasher.update(bytes);
+    hex::encode(hasher.finalize())
+}
+
+pub fn sha224(bytes: &[u8]) -> String {
+    let mut hasher = sha2::Sha224::new();
+    hasher.update(bytes);
+    hex::encode(hasher.finalize())
+}
 
 pub fn sha256(bytes: &[u8]) -> String {
     let mut hasher = sha2::Sha256::new();
@@ -6,6 +28,49 @@ pub fn sha256(bytes: &[u8]) -> String {
     hex::encode(hasher.finalize())
 }
 
+/// Hash everything that can be read from `reader`, returns the sha256 and the number of bytes
+pub fn sha256_reader<R: Read>(mut reader: R) -> io::Result<(String, u64)> {
+    let mut hasher = sha2::Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+    Ok((hex::encode(hasher.finalize()), size))
+}
+
+/// Hash a file without loading it into memory, returns the sha256 and the size of the file
+pub async fn sha256_file(path: &Path) -> Result<(String, u64)> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| anyhow!("Failed to open file: {path:?}"))?;
+    let mut hasher = sha2::Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+    Ok((hex::encode(hasher.finalize()), size))
+}
+
+pub fn sha384(bytes: &[u8]) -> String {
+    let mut hasher = sha2::Sha384::new();
+    hasher.update(bytes);
+    hex::encode(hasher.finalize())
+}
+
 pub fn sha512(bytes: &[u8]) -> String {
     let mut hasher = sha2::Sha512::new();
     hasher.update(bytes);
@@ -18,10 +83,62 @@ pub fn blake2b(bytes: &[u8]) -> String {
     hex::encode(hasher.finalize())
 }
 
+/// The POSIX `cksum` CRC, formatted as decimal number like the `cksum` utility does
+pub fn cksum(bytes: &[u8]) -> String {
+    fn update(crc: u32, byte: u8) -> u32 {
+        let mut crc = crc ^ (u32::from(byte) << 24);
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    }
+
+    let mut crc = bytes.iter().fold(0, |crc, byte| update(crc, *byte));
+    // the length of the input is appended, least significant byte first
+    let mut len = bytes.len();
+    while len > 0 {
+        crc = update(crc, len as u8);
+        len >>= 8;
+    }
+    (!crc).to_string()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_md5() {
+        let hash = md5(b"hello world");
+        assert_eq!(hash, "5eb63bbbe01eeed093cb22bb8f5acdc3");
+    }
+
+    #[test]
+    fn test_sha256_reader() {
+        let (hash, size) = sha256_reader(&b"hello world"[..]).unwrap();
+        assert_eq!(hash, sha256(b"hello world"));
+        assert_eq!(size, 11);
+    }
+
+    #[test]
+    fn test_sha1() {
+        let hash = sha1(b"hello world");
+        assert_eq!(hash, "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed");
+    }
+
+    #[test]
+    fn test_sha224() {
+        let hash = sha224(b"hello world");
+        assert_eq!(
+            hash,
+            "2f05477fc24bb4faefd86517156dafdecec45b8ad3cf2522a563582b"
+        );
+    }
+
     #[test]
     fn test_sha256() {
         let hash = sha256(b"hello world");
@@ -31,6 +148,12 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_sha384() {
+        let hash = sha384(b"hello world");
+        assert_eq!(hash, "fdbd8e75a67f29f701a4e040385e2e23986303ea10239211af907fcbb83578b3e417cb71ce646efd0819dd8c088de1bd");
+    }
+
     #[test]
     fn test_sha512() {
         let hash = sha512(b"hello world");
@@ -42,4 +165,10 @@ mod tests {
         let hash = blake2b(b"hello world");
         assert_eq!(hash, "021ced8799296ceca557832ab941a50b4a11f83478cf141f51f933f653ab9fbcc05a037cddbed06e309bf334942c4e58cdf1a46e237911ccd7fcf9787cbc7fd0");
     }
+
+    #[test]
+    fn test_cksum() {
+        assert_eq!(cksum(b"hello world"), "1135714720");
+        assert_eq!(cksum(b""), "4294967295");
+    }
 }
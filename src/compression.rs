@@ -1,27 +1,923 @@
+use crate::chksums;
 use crate::errors::*;
+use digest::Digest;
 use libflate::gzip::Decoder;
+use ruzstd::decoding::errors::{FrameDecoderError, ReadFrameHeaderError};
+use ruzstd::decoding::{BlockDecodingStrategy, FrameDecoder};
 use std::borrow::Cow;
-use std::io::Read;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::thread;
 
 pub const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
 pub const LZMA_MAGIC: &[u8] = &[0xfd, 0x37, 0x7a, 0x58];
+pub const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+pub const BZIP2_MAGIC: &[u8] = b"BZh";
+pub const LZ4_MAGIC: &[u8] = &[0x04, 0x22, 0x4d, 0x18];
+pub const LZIP_MAGIC: &[u8] = b"LZIP";
+/// The `.lzma` header has no magic, but the default properties (lc=3, lp=0, pb=2) and dictionary sizes start like this
+pub const LZMA_ALONE_MAGIC: &[u8] = &[0x5d, 0x00, 0x00];
 
-pub fn decompress(mut bytes: &[u8]) -> Result<Cow<[u8]>> {
-    if bytes.starts_with(GZIP_MAGIC) {
-        debug!("Detected gzip compression");
-        let mut decoder = Decoder::new(bytes)?;
-        let mut buf = Vec::new();
-        decoder.read_to_end(&mut buf)?;
-        Ok(Cow::Owned(buf))
-    } else if bytes.starts_with(LZMA_MAGIC) {
-        debug!("Detected lzma compression");
-        let mut buf = Vec::new();
-        lzma_rs::xz_decompress(&mut bytes, &mut buf)?;
-        Ok(Cow::Owned(buf))
+const XZ_HEADER_SIZE: u64 = 12;
+const XZ_FOOTER_SIZE: u64 = 12;
+const XZ_FOOTER_MAGIC: &[u8] = b"YZ";
+
+const LZIP_HEADER_SIZE: usize = 6;
+const LZIP_TRAILER_SIZE: usize = 20;
+
+/// Size of the chunks handed over by a decoder thread
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Number of chunks buffered before a decoder thread has to wait for the reader
+const CHANNEL_CAPACITY: usize = 4;
+
+/// Below this size the compression ratio is not enforced, small inputs can have arbitrary ratios
+const RATIO_THRESHOLD: u64 = 1024 * 1024;
+
+/// Resource limits for processing compressed input from untrusted sources
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// Maximum number of bytes after decompression
+    pub max_size: u64,
+    /// Maximum ratio of decompressed size to compressed size
+    pub max_ratio: u64,
+    /// Maximum number of entries read from a tar archive
+    pub max_entries: u64,
+    /// Maximum number of bytes decompressed into memory as a whole, like indexes and metadata files
+    pub max_memory: u64,
+}
+
+impl Limits {
+    pub const DEFAULT: Limits = Limits {
+        max_size: 64 * 1024 * 1024 * 1024,
+        max_ratio: 1000,
+        max_entries: 1_000_000,
+        max_memory: 512 * 1024 * 1024,
+    };
+
+    /// Ensure the number of tar entries processed so far is within the limit
+    pub fn check_entries(&self, count: u64) -> Result<()> {
+        if count > self.max_entries {
+            return Err(LimitExceeded::Entries {
+                limit: self.max_entries,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Ensure data of `size` bytes may be read into memory
+    pub fn check_memory(&self, size: u64) -> Result<()> {
+        if size > self.max_memory {
+            return Err(LimitExceeded::Memory {
+                limit: self.max_memory,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// The number of bytes that may be decompressed from `compressed_size` bytes
+    fn allowance(&self, compressed_size: u64) -> (u64, LimitExceeded) {
+        let ratio = compressed_size
+            .saturating_mul(self.max_ratio)
+            .max(RATIO_THRESHOLD);
+        if self.max_size <= ratio {
+            let err = LimitExceeded::Size {
+                limit: self.max_size,
+            };
+            (self.max_size, err)
+        } else {
+            let err = LimitExceeded::Ratio {
+                limit: self.max_ratio,
+            };
+            (ratio, err)
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// The error returned when input exceeds one of the configured `Limits`
+#[derive(Debug, Clone, PartialEq)]
+pub enum LimitExceeded {
+    Size { limit: u64 },
+    Ratio { limit: u64 },
+    Entries { limit: u64 },
+    Memory { limit: u64 },
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::Size { limit } => {
+                write!(f, "Decompressed data exceeds size limit of {limit} bytes")
+            }
+            LimitExceeded::Ratio { limit } => {
+                write!(
+                    f,
+                    "Decompressed data exceeds compression ratio limit of {limit}:1"
+                )
+            }
+            LimitExceeded::Entries { limit } => {
+                write!(f, "Archive exceeds limit of {limit} entries")
+            }
+            LimitExceeded::Memory { limit } => {
+                write!(
+                    f,
+                    "Decompressed data exceeds in-memory size limit of {limit} bytes"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+impl LimitExceeded {
+    /// Find a `LimitExceeded` in an error chain, also if it has been passed through `io::Error`
+    pub fn find(err: &Error) -> Option<&LimitExceeded> {
+        err.chain().find_map(|cause| {
+            cause.downcast_ref::<LimitExceeded>().or_else(|| {
+                cause
+                    .downcast_ref::<io::Error>()
+                    .and_then(|err| err.get_ref())
+                    .and_then(|inner| inner.downcast_ref::<LimitExceeded>())
+            })
+        })
+    }
+}
+
+/// Fails reading once more data than allowed has been decompressed
+struct LimitedReader<R> {
+    inner: R,
+    remaining: u64,
+    err: LimitExceeded,
+}
+
+impl<R> LimitedReader<R> {
+    fn new(inner: R, limits: &Limits, compressed_size: u64) -> Self {
+        let (remaining, err) = limits.allowance(compressed_size);
+        LimitedReader {
+            inner,
+            remaining,
+            err,
+        }
+    }
+
+    /// Limit data that is decompressed into memory as a whole, also enforcing `max_memory`
+    fn in_memory(inner: R, limits: &Limits, compressed_size: u64) -> Self {
+        let mut reader = Self::new(inner, limits, compressed_size);
+        if limits.max_memory < reader.remaining {
+            reader.remaining = limits.max_memory;
+            reader.err = LimitExceeded::Memory {
+                limit: limits.max_memory,
+            };
+        }
+        reader
+    }
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.remaining = self
+            .remaining
+            .checked_sub(n as u64)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, self.err.clone()))?;
+        Ok(n)
+    }
+}
+
+struct HashingState<R> {
+    inner: R,
+    /// The read position as seen by the decoder
+    pos: u64,
+    /// Everything before this offset has been hashed
+    hashed: u64,
+    hasher: Option<sha2::Sha256>,
+    /// The first bytes of the input, format detection reads them twice
+    head: Vec<u8>,
+}
+
+/// Hashes the raw input while a decoder is reading it
+///
+/// Each byte is hashed the first time it's read in order, so the decoded data is guaranteed to be
+/// made of the hashed bytes. Reading ahead (like the xz index) is passed through, going back to
+/// already hashed data is only possible for the first `CHUNK_SIZE` bytes, which are kept around.
+pub struct HashingReader<R> {
+    state: Arc<Mutex<HashingState<R>>>,
+}
+
+impl<R> Clone for HashingReader<R> {
+    fn clone(&self) -> Self {
+        HashingReader {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<R: Read + Seek> HashingReader<R> {
+    pub fn new(inner: R) -> Self {
+        let state = HashingState {
+            inner,
+            pos: 0,
+            hashed: 0,
+            hasher: Some(sha2::Sha256::new()),
+            head: Vec::new(),
+        };
+        HashingReader {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    fn lock(&self) -> io::Result<MutexGuard<'_, HashingState<R>>> {
+        self.state
+            .lock()
+            .map_err(|_| io::Error::other("Hashing reader has been poisoned"))
+    }
+
+    /// Hash the rest of the input that wasn't read by the decoder
+    ///
+    /// Returns the sha256 and the size of the input, reading through this reader fails afterwards.
+    pub fn finish(&self) -> io::Result<(String, u64)> {
+        let mut state = self.lock()?;
+        let mut hasher = state
+            .hasher
+            .take()
+            .ok_or_else(|| io::Error::other("Input has already been hashed"))?;
+        let hashed = state.hashed;
+        state.inner.seek(SeekFrom::Start(hashed))?;
+        let remaining = io::copy(&mut state.inner, &mut hasher)?;
+        Ok((hex::encode(hasher.finalize()), hashed + remaining))
+    }
+}
+
+impl<R: Read + Seek> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.lock()?;
+        let state = &mut *state;
+        let Some(hasher) = state.hasher.as_mut() else {
+            return Err(io::Error::other("Input has already been hashed"));
+        };
+
+        if let Some(head) = state
+            .head
+            .get(state.pos as usize..)
+            .filter(|h| !h.is_empty())
+        {
+            let n = head.len().min(buf.len());
+            buf[..n].copy_from_slice(&head[..n]);
+            state.pos += n as u64;
+            return Ok(n);
+        }
+        if state.pos < state.hashed {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Refusing to read already hashed data again",
+            ));
+        }
+
+        state.inner.seek(SeekFrom::Start(state.pos))?;
+        let n = state.inner.read(buf)?;
+        if state.pos == state.hashed {
+            hasher.update(&buf[..n]);
+            let keep = CHUNK_SIZE.saturating_sub(state.head.len()).min(n);
+            state.head.extend_from_slice(&buf[..keep]);
+            state.hashed += n as u64;
+        }
+        state.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for HashingReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let mut state = self.lock()?;
+        let pos = match pos {
+            SeekFrom::Current(offset) => {
+                let pos = state.pos.checked_add_signed(offset).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "Invalid seek position")
+                })?;
+                SeekFrom::Start(pos)
+            }
+            pos => pos,
+        };
+        state.pos = state.inner.seek(pos)?;
+        Ok(state.pos)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Xz,
+    Zstd,
+    Bzip2,
+    Lz4,
+    Lzip,
+    Lzma,
+    None,
+}
+
+impl Compression {
+    /// Detect the compression format by its magic bytes
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if bytes.starts_with(LZMA_MAGIC) {
+            Compression::Xz
+        } else if bytes.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else if bytes.starts_with(BZIP2_MAGIC) {
+            Compression::Bzip2
+        } else if bytes.starts_with(LZ4_MAGIC) {
+            Compression::Lz4
+        } else if bytes.starts_with(LZIP_MAGIC) {
+            Compression::Lzip
+        } else if bytes.starts_with(LZMA_ALONE_MAGIC) {
+            Compression::Lzma
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Detect the compression format of a reader, the read position is restored afterwards
+    ///
+    /// Also returns the number of bytes that are left to read.
+    pub fn detect_reader<R: Read + Seek>(reader: &mut R) -> Result<(Self, u64)> {
+        let pos = reader.stream_position()?;
+        let len = reader.seek(SeekFrom::End(0))?.saturating_sub(pos);
+        reader.seek(SeekFrom::Start(pos))?;
+
+        let mut magic = Vec::with_capacity(LZIP_HEADER_SIZE);
+        reader
+            .by_ref()
+            .take(LZIP_HEADER_SIZE as u64)
+            .read_to_end(&mut magic)?;
+        reader.seek(SeekFrom::Start(pos))?;
+        Ok((Self::detect(&magic), len))
+    }
+}
+
+/// Hands decompressed data from a decoder thread over to a `ChannelReader`
+struct ChannelWriter {
+    tx: mpsc::SyncSender<io::Result<Vec<u8>>>,
+    buf: Vec<u8>,
+}
+
+impl ChannelWriter {
+    fn send(&mut self, chunk: Vec<u8>) -> io::Result<()> {
+        self.tx
+            .send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Reader has been dropped"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(CHUNK_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+        if self.buf.len() >= CHUNK_SIZE {
+            let chunk = std::mem::replace(&mut self.buf, Vec::with_capacity(CHUNK_SIZE));
+            self.send(chunk)?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            let chunk = std::mem::take(&mut self.buf);
+            self.send(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the chunks produced by a decoder thread, an empty chunk marks the end of the stream
+struct ChannelReader {
+    rx: mpsc::Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+    finished: bool,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos >= self.chunk.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            match self.rx.recv() {
+                Ok(Ok(chunk)) => {
+                    self.finished = chunk.is_empty();
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                Ok(Err(err)) => return Err(err),
+                Err(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Decoder thread terminated unexpectedly",
+                    ))
+                }
+            }
+        }
+
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Run a decoder that can only write its output in a separate thread and read from it
+///
+/// The channel is bounded, so the decoder is paused until the reader catches up.
+fn spawn_decoder<F>(decode: F) -> Box<dyn Read + Send>
+where
+    F: FnOnce(&mut ChannelWriter) -> Result<()> + Send + 'static,
+{
+    let (tx, rx) = mpsc::sync_channel(CHANNEL_CAPACITY);
+    thread::spawn(move || {
+        let mut writer = ChannelWriter {
+            tx: tx.clone(),
+            buf: Vec::with_capacity(CHUNK_SIZE),
+        };
+        let result = decode(&mut writer)
+            .and_then(|_| writer.flush().map_err(Error::from))
+            .map(|_| Vec::new())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{err:#}")));
+        // if the reader has been dropped nobody is interested in the result
+        let _ = tx.send(result);
+    });
+    Box::new(ChannelReader {
+        rx,
+        chunk: Vec::new(),
+        pos: 0,
+        finished: false,
+    })
+}
+
+/// Create a streaming decoder for the compression format detected in `reader`
+///
+/// The decompressed data is never held in memory as a whole, reading fails once it exceeds `limits`.
+/// The reader should be buffered.
+pub fn reader<R: Read + Seek + Send + 'static>(
+    mut reader: R,
+    limits: &Limits,
+) -> Result<Box<dyn Read + Send>> {
+    let (compression, compressed_size) = Compression::detect_reader(&mut reader)?;
+    debug!("Detected compression: {compression:?}");
+    let reader: Box<dyn Read + Send> = match compression {
+        Compression::Gzip => Box::new(GzipDecoder::new(BufReader::new(reader))?),
+        Compression::Xz => spawn_decoder(move |out| xz_decompress(reader, out)),
+        Compression::Zstd => Box::new(ZstdDecoder::new(BufReader::new(reader))?),
+        Compression::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+        Compression::Lz4 => Box::new(Lz4Decoder::new(BufReader::new(reader))),
+        Compression::Lzip => spawn_decoder(move |out| lzip_decompress(reader, out)),
+        Compression::Lzma => spawn_decoder(move |out| {
+            lzma_rs::lzma_decompress(&mut BufReader::new(reader), out)?;
+            Ok(())
+        }),
+        Compression::None => Box::new(reader),
+    };
+    Ok(Box::new(LimitedReader::new(
+        reader,
+        limits,
+        compressed_size,
+    )))
+}
+
+/// Create a streaming decoder for the compression format detected in `bytes`
+///
+/// Reading fails once the decompressed data exceeds `limits`.
+pub fn decoder<'a>(bytes: &'a [u8], limits: &Limits) -> Result<Box<dyn Read + 'a>> {
+    let compression = Compression::detect(bytes);
+    let reader: Box<dyn Read + 'a> = match compression {
+        Compression::Gzip => Box::new(GzipDecoder::new(bytes)?),
+        Compression::Zstd => Box::new(ZstdDecoder::new(bytes)?),
+        Compression::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(bytes)),
+        Compression::Lz4 => Box::new(Lz4Decoder::new(bytes)),
+        Compression::None => Box::new(bytes),
+        // these are decoded in a separate thread, which needs its own copy of the input
+        Compression::Xz | Compression::Lzip | Compression::Lzma => {
+            return reader(io::Cursor::new(bytes.to_vec()), limits).map(|r| r as Box<dyn Read>);
+        }
+    };
+    debug!("Detected compression: {compression:?}");
+    Ok(Box::new(LimitedReader::new(
+        reader,
+        limits,
+        bytes.len() as u64,
+    )))
+}
+
+/// Decodes all members of a gzip file
+///
+/// Unlike `libflate::gzip::MultiDecoder`, data after the last member is an error even if it's
+/// too short for a gzip header.
+struct GzipDecoder<R> {
+    decoder: Option<Decoder<R>>,
+}
+
+impl<R: BufRead> GzipDecoder<R> {
+    fn new(reader: R) -> io::Result<Self> {
+        let decoder = Decoder::new(reader)?;
+        Ok(GzipDecoder {
+            decoder: Some(decoder),
+        })
+    }
+}
+
+impl<R: BufRead> Read for GzipDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let Some(decoder) = self.decoder.as_mut() else {
+                return Ok(0);
+            };
+            let n = decoder.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+
+            // the member is complete, anything that follows needs to be another member
+            let Some(mut reader) = self.decoder.take().map(Decoder::into_inner) else {
+                return Ok(0);
+            };
+            if reader.fill_buf()?.is_empty() {
+                return Ok(0);
+            }
+            let decoder = Decoder::new(reader).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unexpected trailing data after gzip member: {err}"),
+                )
+            })?;
+            self.decoder = Some(decoder);
+        }
+    }
+}
+
+/// Decodes all frames of a zstd file, skippable frames are ignored
+struct ZstdDecoder<R> {
+    reader: R,
+    decoder: FrameDecoder,
+}
+
+impl<R: BufRead> ZstdDecoder<R> {
+    fn new(reader: R) -> io::Result<Self> {
+        let mut decoder = ZstdDecoder {
+            reader,
+            decoder: FrameDecoder::new(),
+        };
+        if !decoder.next_frame()? {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Missing zstd frame",
+            ));
+        }
+        Ok(decoder)
+    }
+
+    /// Start decoding the next frame, returns false at the end of the input
+    fn next_frame(&mut self) -> io::Result<bool> {
+        loop {
+            if self.reader.fill_buf()?.is_empty() {
+                return Ok(false);
+            }
+            match self.decoder.init(&mut self.reader) {
+                Ok(()) => return Ok(true),
+                Err(FrameDecoderError::ReadFrameHeaderError(ReadFrameHeaderError::SkipFrame {
+                    length,
+                    ..
+                })) => {
+                    let length = u64::from(length);
+                    let skipped = io::copy(&mut (&mut self.reader).take(length), &mut io::sink())?;
+                    if skipped != length {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "Unexpected end of skippable zstd frame",
+                        ));
+                    }
+                }
+                Err(err) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid zstd frame: {err}"),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Read for ZstdDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            while self.decoder.can_collect() < buf.len() && !self.decoder.is_finished() {
+                let needed = buf.len() - self.decoder.can_collect();
+                self.decoder
+                    .decode_blocks(&mut self.reader, BlockDecodingStrategy::UptoBytes(needed))
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            }
+
+            let n = self.decoder.read(buf)?;
+            if n > 0 {
+                return Ok(n);
+            }
+            if !self.next_frame()? {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+/// Decodes all frames of an lz4 file
+///
+/// `lz4_flex::frame::FrameDecoder` signals the end of each frame like the end of the file.
+struct Lz4Decoder<R: Read> {
+    decoder: lz4_flex::frame::FrameDecoder<R>,
+}
+
+impl<R: BufRead> Lz4Decoder<R> {
+    fn new(reader: R) -> Self {
+        let decoder = lz4_flex::frame::FrameDecoder::new(reader);
+        Lz4Decoder { decoder }
+    }
+}
+
+impl<R: BufRead> Read for Lz4Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.decoder.read(buf)?;
+            if n > 0 || buf.is_empty() || self.decoder.get_mut().fill_buf()?.is_empty() {
+                return Ok(n);
+            }
+        }
+    }
+}
+
+/// Parse a variable-length integer as used in the xz index
+fn xz_varint(bytes: &mut &[u8]) -> Result<u64> {
+    let mut value = 0;
+    for i in 0..9 {
+        let (byte, rest) = bytes.split_first().context("Unexpected end of xz index")?;
+        *bytes = rest;
+        value |= u64::from(byte & 0x7f) << (i * 7);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("Invalid integer in xz index")
+}
+
+/// Move to `pos`, moving forward is done by reading so the input is consumed front to back
+fn skip_to<R: Read + Seek>(reader: &mut R, pos: u64) -> Result<()> {
+    let current = reader.stream_position()?;
+    if current <= pos {
+        io::copy(&mut reader.take(pos - current), &mut io::sink())?;
     } else {
+        reader.seek(SeekFrom::Start(pos))?;
+    }
+    Ok(())
+}
+
+/// Decompress all streams of an xz file
+///
+/// lzma-rs only decodes a single stream, so the streams and the padding between them are
+/// located starting from the end, using the backward size of the footer and the index.
+fn xz_decompress<R: Read + Seek, W: Write>(mut reader: R, out: &mut W) -> Result<()> {
+    let mut streams = Vec::new();
+    let mut end = reader.seek(SeekFrom::End(0))?;
+    while end > 0 {
+        // stream padding is a multiple of four null bytes
+        let mut word = [0u8; 4];
+        let word_start = end
+            .checked_sub(4)
+            .context("Unexpected trailing data after xz stream")?;
+        reader.seek(SeekFrom::Start(word_start))?;
+        reader.read_exact(&mut word)?;
+        if word == [0; 4] {
+            end = word_start;
+            continue;
+        }
+
+        let footer_start = end
+            .checked_sub(XZ_FOOTER_SIZE)
+            .context("Unexpected trailing data after xz stream")?;
+        let mut footer = [0u8; XZ_FOOTER_SIZE as usize];
+        reader.seek(SeekFrom::Start(footer_start))?;
+        reader.read_exact(&mut footer)?;
+        if !footer.ends_with(XZ_FOOTER_MAGIC) {
+            bail!("Unexpected trailing data after xz stream");
+        }
+
+        let backward_size = u32::from_le_bytes(footer[4..8].try_into()?);
+        let index_size = (u64::from(backward_size) + 1) * 4;
+        let index_start = footer_start
+            .checked_sub(index_size)
+            .context("Invalid backward size in xz footer")?;
+        let mut index = vec![0u8; index_size as usize];
+        reader.seek(SeekFrom::Start(index_start))?;
+        reader.read_exact(&mut index)?;
+
+        let mut index = &index[..];
+        if xz_varint(&mut index)? != 0 {
+            bail!("Invalid xz index indicator");
+        }
+        let mut blocks_size = 0u64;
+        for _ in 0..xz_varint(&mut index)? {
+            let unpadded_size = xz_varint(&mut index)?;
+            let _uncompressed_size = xz_varint(&mut index)?;
+            blocks_size = unpadded_size
+                .checked_next_multiple_of(4)
+                .and_then(|size| blocks_size.checked_add(size))
+                .context("Invalid block size in xz index")?;
+        }
+
+        let start = index_start
+            .checked_sub(blocks_size)
+            .and_then(|start| start.checked_sub(XZ_HEADER_SIZE))
+            .context("Invalid xz index")?;
+        streams.push((start, end - start));
+        end = start;
+    }
+
+    if streams.is_empty() {
+        bail!("Missing xz stream");
+    }
+    for (start, size) in streams.into_iter().rev() {
+        skip_to(&mut reader, start)?;
+        lzma_rs::xz_decompress(&mut BufReader::new((&mut reader).take(size)), out)?;
+    }
+    Ok(())
+}
+
+/// Forwards decompressed data while keeping track of its size and crc32
+struct Crc32Writer<'a, W> {
+    inner: &'a mut W,
+    hasher: crc32fast::Hasher,
+    size: u64,
+}
+
+impl<W: Write> Write for Crc32Writer<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decompress all members of an lzip file
+///
+/// The size of each member is stored in its trailer, so they are located starting from the end.
+fn lzip_decompress<R: Read + Seek, W: Write>(mut reader: R, out: &mut W) -> Result<()> {
+    let mut members = Vec::new();
+    let mut end = reader.seek(SeekFrom::End(0))?;
+    while end > 0 {
+        let trailer_start = end
+            .checked_sub(LZIP_TRAILER_SIZE as u64)
+            .context("Unexpected end of lzip file")?;
+        let mut trailer = [0u8; LZIP_TRAILER_SIZE];
+        reader.seek(SeekFrom::Start(trailer_start))?;
+        reader.read_exact(&mut trailer)?;
+
+        let member_size = u64::from_le_bytes(trailer[12..].try_into()?);
+        let start = Some(member_size)
+            .filter(|size| *size >= (LZIP_HEADER_SIZE + LZIP_TRAILER_SIZE) as u64)
+            .and_then(|size| end.checked_sub(size))
+            .with_context(|| anyhow!("Invalid member size in lzip trailer: {member_size}"))?;
+        members.push((start, member_size));
+        end = start;
+    }
+
+    for (start, size) in members.into_iter().rev() {
+        skip_to(&mut reader, start)?;
+        lzip_member(BufReader::new((&mut reader).take(size)), size, out)?;
+    }
+    Ok(())
+}
+
+fn lzip_member<R: BufRead, W: Write>(mut member: R, size: u64, out: &mut W) -> Result<()> {
+    let mut header = [0u8; LZIP_HEADER_SIZE];
+    member.read_exact(&mut header)?;
+
+    if !header.starts_with(LZIP_MAGIC) {
+        bail!("Invalid lzip member header: {header:?}");
+    }
+    if header[4] != 1 {
+        bail!("Unsupported lzip version: {}", header[4]);
+    }
+    let exponent = header[5] & 0x1f;
+    if !(12..=29).contains(&exponent) {
+        bail!("Invalid dictionary size in lzip header: {:#x}", header[5]);
+    }
+    let base = 1u32 << exponent;
+    let dict_size = base - (base / 16) * u32::from(header[5] >> 5);
+
+    // lzip uses a raw lzma stream with fixed properties, build the .lzma header for it
+    let mut lzma_header = [0xff; 13];
+    lzma_header[0] = 0x5d;
+    lzma_header[1..5].copy_from_slice(&dict_size.to_le_bytes());
+
+    let data_size = size - (LZIP_HEADER_SIZE + LZIP_TRAILER_SIZE) as u64;
+    let mut data = (&mut member).take(data_size);
+    let mut writer = Crc32Writer {
+        inner: out,
+        hasher: crc32fast::Hasher::new(),
+        size: 0,
+    };
+    lzma_rs::lzma_decompress(&mut lzma_header.chain(&mut data), &mut writer)?;
+
+    let mut trailer = [0u8; LZIP_TRAILER_SIZE];
+    member.read_exact(&mut trailer)?;
+    let crc = u32::from_le_bytes(trailer[..4].try_into()?);
+    let decompressed_size = u64::from_le_bytes(trailer[4..12].try_into()?);
+    if writer.size != decompressed_size {
+        bail!(
+            "Size mismatch in lzip member: trailer={decompressed_size}, decompressed={}",
+            writer.size
+        );
+    }
+    if writer.hasher.finalize() != crc {
+        bail!("Checksum mismatch in lzip member");
+    }
+    Ok(())
+}
+
+/// Decompress into memory, the decompressed size is also bounded by `max_memory`
+pub fn decompress<'a>(bytes: &'a [u8], limits: &Limits) -> Result<Cow<'a, [u8]>> {
+    if Compression::detect(bytes) == Compression::None {
         debug!("Did not detect any compression, using as tar directly");
-        Ok(Cow::Borrowed(bytes))
+        if bytes.len() as u64 > limits.max_size {
+            return Err(LimitExceeded::Size {
+                limit: limits.max_size,
+            }
+            .into());
+        }
+        return Ok(Cow::Borrowed(bytes));
     }
+
+    let mut buf = Vec::new();
+    LimitedReader::in_memory(decoder(bytes, limits)?, limits, bytes.len() as u64)
+        .read_to_end(&mut buf)?;
+    Ok(Cow::Owned(buf))
+}
+
+/// Hash the decompressed content of a file, without holding it in memory
+///
+/// Returns the sha256 and the size of the decompressed data.
+pub async fn sha256_decompressed(path: &Path, limits: &Limits) -> Result<(String, u64)> {
+    let path = path.to_owned();
+    let limits = *limits;
+    tokio::task::spawn_blocking(move || {
+        let file =
+            std::fs::File::open(&path).with_context(|| anyhow!("Failed to open file: {path:?}"))?;
+        let reader = reader(BufReader::new(file), &limits)?;
+        let hashed = chksums::sha256_reader(reader)
+            .with_context(|| anyhow!("Failed to decompress file: {path:?}"))?;
+        Ok(hashed)
+    })
+    .await?
+}
+
+/// Hash a file and its decompressed content, while reading the file only once
+///
+/// The decompressed data is decoded from the same bytes that are hashed, so a file can't be swapped
+/// in between. Returns the sha256 and the size of the file, followed by those of the decompressed data.
+pub async fn sha256_file_and_decompressed(
+    path: &Path,
+    limits: &Limits,
+) -> Result<((String, u64), (String, u64))> {
+    let path = path.to_owned();
+    let limits = *limits;
+    tokio::task::spawn_blocking(move || {
+        let file =
+            std::fs::File::open(&path).with_context(|| anyhow!("Failed to open file: {path:?}"))?;
+        let hashing = HashingReader::new(file);
+        let reader = reader(BufReader::new(hashing.clone()), &limits)?;
+        let decompressed = chksums::sha256_reader(reader)
+            .with_context(|| anyhow!("Failed to decompress file: {path:?}"))?;
+        let raw = hashing
+            .finish()
+            .with_context(|| anyhow!("Failed to hash file: {path:?}"))?;
+        Ok((raw, decompressed))
+    })
+    .await?
 }
 
 #[cfg(test)]
@@ -35,7 +931,7 @@ mod tests {
             0xc9, 0x57, 0x28, 0xcf, 0x2f, 0xca, 0x49, 0x01, 0x00, 0x85, 0x11, 0x4a, 0x0d, 0x0b,
             0x00, 0x00, 0x00,
         ];
-        let txt = decompress(&bytes).unwrap();
+        let txt = decompress(&bytes, &Limits::DEFAULT).unwrap();
         assert_eq!(&*txt, b"hello world");
     }
 
@@ -50,7 +946,334 @@ mod tests {
             0x00, 0x00, 0x00, 0x04, 0x59, 0x5a,
         ];
 
-        let txt = decompress(&bytes).unwrap();
+        let txt = decompress(&bytes, &Limits::DEFAULT).unwrap();
+        assert_eq!(&*txt, b"hello world");
+    }
+
+    #[test]
+    fn test_decompress_zstd() {
+        let bytes = [
+            0x28, 0xb5, 0x2f, 0xfd, 0x04, 0x58, 0x59, 0x00, 0x00, 0x68, 0x65, 0x6c, 0x6c, 0x6f,
+            0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64, 0x68, 0x69, 0x1e, 0xb2,
+        ];
+        let txt = decompress(&bytes, &Limits::DEFAULT).unwrap();
+        assert_eq!(&*txt, b"hello world");
+    }
+
+    #[test]
+    fn test_decompress_bzip2() {
+        let bytes = [
+            0x42, 0x5a, 0x68, 0x39, 0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0x44, 0xf7, 0x13, 0x78,
+            0x00, 0x00, 0x01, 0x91, 0x80, 0x40, 0x00, 0x06, 0x44, 0x90, 0x80, 0x20, 0x00, 0x22,
+            0x03, 0x34, 0x84, 0x30, 0x21, 0xb6, 0x81, 0x54, 0x27, 0x8b, 0xb9, 0x22, 0x9c, 0x28,
+            0x48, 0x22, 0x7b, 0x89, 0xbc, 0x00,
+        ];
+        let txt = decompress(&bytes, &Limits::DEFAULT).unwrap();
+        assert_eq!(&*txt, b"hello world");
+    }
+
+    #[test]
+    fn test_decompress_lz4() {
+        let bytes = [
+            0x04, 0x22, 0x4d, 0x18, 0x64, 0x40, 0xa7, 0x0b, 0x00, 0x00, 0x80, 0x68, 0x65, 0x6c,
+            0x6c, 0x6f, 0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64, 0x00, 0x00, 0x00, 0x00, 0x22, 0x66,
+            0xbb, 0xce,
+        ];
+        let txt = decompress(&bytes, &Limits::DEFAULT).unwrap();
+        assert_eq!(&*txt, b"hello world");
+    }
+
+    #[test]
+    fn test_detect_none() {
+        assert_eq!(Compression::detect(b"hello world"), Compression::None);
+        let txt = decompress(b"hello world", &Limits::DEFAULT).unwrap();
+        assert!(matches!(txt, Cow::Borrowed(b"hello world")));
+    }
+
+    #[test]
+    fn test_decompress_lzma() {
+        let bytes = [
+            0x5d, 0x00, 0x00, 0x80, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00,
+            0x34, 0x19, 0x49, 0xee, 0x8d, 0xe9, 0x17, 0x89, 0x3a, 0x33, 0x60, 0x05, 0xf7, 0xcf,
+            0x64, 0xff, 0xfb, 0x78, 0x20, 0x00,
+        ];
+        let txt = decompress(&bytes, &Limits::DEFAULT).unwrap();
+        assert_eq!(&*txt, b"hello world");
+    }
+
+    #[test]
+    fn test_decompress_lzip() {
+        let bytes = [
+            0x4c, 0x5a, 0x49, 0x50, 0x01, 0x17, 0x00, 0x34, 0x19, 0x49, 0xee, 0x8d, 0xe9, 0x17,
+            0x89, 0x3a, 0x33, 0x60, 0x05, 0xf7, 0xcf, 0x64, 0xff, 0xfb, 0x78, 0x20, 0x00, 0x85,
+            0x11, 0x4a, 0x0d, 0x0b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x2f, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let txt = decompress(&bytes, &Limits::DEFAULT).unwrap();
+        assert_eq!(&*txt, b"hello world");
+
+        let mut corrupted = bytes;
+        corrupted[27] ^= 0xff;
+        assert!(decompress(&corrupted, &Limits::DEFAULT).is_err());
+    }
+
+    #[test]
+    fn test_decompress_lzip_multi_member() {
+        let bytes = [
+            0x4c, 0x5a, 0x49, 0x50, 0x01, 0x17, 0x00, 0x34, 0x19, 0x49, 0xee, 0x8d, 0xe9, 0x3f,
+            0x0a, 0xdf, 0xff, 0xff, 0xdd, 0x12, 0x00, 0x00, 0xf6, 0xf9, 0x81, 0xed, 0x06, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x2a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x4c, 0x5a, 0x49, 0x50, 0x01, 0x17, 0x00, 0x3b, 0x9b, 0xca, 0xab, 0x74, 0x0b, 0x4b,
+            0x42, 0xff, 0xff, 0xfc, 0xb8, 0x70, 0x00, 0x43, 0x11, 0x77, 0x3a, 0x05, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x29, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let txt = decompress(&bytes, &Limits::DEFAULT).unwrap();
+        assert_eq!(&*txt, b"hello world");
+    }
+
+    #[test]
+    fn test_reader_streams_in_chunks() {
+        // xz is decoded in a separate thread, make sure everything arrives in order
+        let data = (0..CHUNK_SIZE * 3 + 17)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        let mut compressed = Vec::new();
+        lzma_rs::xz_compress(&mut &data[..], &mut compressed).unwrap();
+        assert_eq!(Compression::detect(&compressed), Compression::Xz);
+
+        let mut reader = reader(io::Cursor::new(compressed), &Limits::DEFAULT).unwrap();
+        let (sha256, size) = chksums::sha256_reader(&mut reader).unwrap();
+        assert_eq!(sha256, chksums::sha256(&data));
+        assert_eq!(size, data.len() as u64);
+    }
+
+    #[test]
+    fn test_reader_reports_errors() {
+        let mut compressed = Vec::new();
+        lzma_rs::xz_compress(&mut &b"hello world"[..], &mut compressed).unwrap();
+        let len = compressed.len();
+        compressed.truncate(len - 8);
+
+        let mut reader = reader(io::Cursor::new(compressed), &Limits::DEFAULT).unwrap();
+        let mut buf = Vec::new();
+        assert!(reader.read_to_end(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_limit_size() {
+        let data = vec![0; 4096];
+        let mut compressed = Vec::new();
+        lzma_rs::xz_compress(&mut &data[..], &mut compressed).unwrap();
+
+        let limits = Limits {
+            max_size: 1000,
+            ..Default::default()
+        };
+        let err = decompress(&compressed, &limits).unwrap_err();
+        assert_eq!(
+            LimitExceeded::find(&err),
+            Some(&LimitExceeded::Size { limit: 1000 })
+        );
+        let err = decompress(&data, &limits).unwrap_err();
+        assert_eq!(
+            LimitExceeded::find(&err),
+            Some(&LimitExceeded::Size { limit: 1000 })
+        );
+
+        let mut reader = reader(io::Cursor::new(compressed), &limits).unwrap();
+        let err = Error::from(chksums::sha256_reader(&mut reader).unwrap_err());
+        assert_eq!(
+            LimitExceeded::find(&err),
+            Some(&LimitExceeded::Size { limit: 1000 })
+        );
+    }
+
+    #[test]
+    fn test_limit_memory() {
+        let data = vec![0; 4096];
+        let compressed = gzip(&data);
+
+        let limits = Limits {
+            max_memory: 1000,
+            ..Default::default()
+        };
+        let err = decompress(&compressed, &limits).unwrap_err();
+        assert_eq!(
+            LimitExceeded::find(&err),
+            Some(&LimitExceeded::Memory { limit: 1000 })
+        );
+        let err = limits.check_memory(4096).unwrap_err();
+        assert_eq!(
+            LimitExceeded::find(&err),
+            Some(&LimitExceeded::Memory { limit: 1000 })
+        );
+
+        // streaming is only bounded by the size limit
+        let mut reader = reader(io::Cursor::new(compressed), &limits).unwrap();
+        let (_, size) = chksums::sha256_reader(&mut reader).unwrap();
+        assert_eq!(size, data.len() as u64);
+    }
+
+    #[test]
+    fn test_limit_ratio() {
+        let data = vec![0; 2 * RATIO_THRESHOLD as usize];
+        let mut encoder = libflate::gzip::Encoder::new(Vec::new()).unwrap();
+        io::copy(&mut &data[..], &mut encoder).unwrap();
+        let compressed = encoder.finish().into_result().unwrap();
+
+        let limits = Limits {
+            max_ratio: 10_000,
+            ..Default::default()
+        };
+        let txt = decompress(&compressed, &limits).unwrap();
+        assert_eq!(txt.len(), data.len());
+
+        let limits = Limits {
+            max_ratio: 100,
+            ..Default::default()
+        };
+        let err = decompress(&compressed, &limits).unwrap_err();
+        assert_eq!(
+            LimitExceeded::find(&err),
+            Some(&LimitExceeded::Ratio { limit: 100 })
+        );
+    }
+
+    #[test]
+    fn test_limit_entries() {
+        let limits = Limits {
+            max_entries: 2,
+            ..Default::default()
+        };
+        limits.check_entries(2).unwrap();
+        let err = limits.check_entries(3).unwrap_err();
+        assert_eq!(
+            LimitExceeded::find(&err),
+            Some(&LimitExceeded::Entries { limit: 2 })
+        );
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = libflate::gzip::Encoder::new(Vec::new()).unwrap();
+        io::copy(&mut &data[..], &mut encoder).unwrap();
+        encoder.finish().into_result().unwrap()
+    }
+
+    fn xz(data: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        lzma_rs::xz_compress(&mut &data[..], &mut compressed).unwrap();
+        compressed
+    }
+
+    fn zstd(data: &[u8]) -> Vec<u8> {
+        ruzstd::encoding::compress_to_vec(data, ruzstd::encoding::CompressionLevel::Fastest)
+    }
+
+    #[test]
+    fn test_decompress_gzip_multi_member() {
+        let bytes = [gzip(b"hello "), gzip(b"world")].concat();
+        let txt = decompress(&bytes, &Limits::DEFAULT).unwrap();
+        assert_eq!(&*txt, b"hello world");
+    }
+
+    #[test]
+    fn test_decompress_gzip_trailing_garbage() {
+        for garbage in [&b"\x00"[..], b"\x1f\x8b", b"this is not a gzip member"] {
+            let bytes = [&gzip(b"hello world"), garbage].concat();
+            assert!(decompress(&bytes, &Limits::DEFAULT).is_err());
+        }
+    }
+
+    #[test]
+    fn test_decompress_xz_multi_stream() {
+        let bytes = [xz(b"hello "), xz(b"world")].concat();
+        let txt = decompress(&bytes, &Limits::DEFAULT).unwrap();
+        assert_eq!(&*txt, b"hello world");
+
+        // stream padding is allowed between and after streams
+        let bytes = [xz(b"hello "), vec![0; 8], xz(b"world"), vec![0; 4]].concat();
+        let txt = decompress(&bytes, &Limits::DEFAULT).unwrap();
+        assert_eq!(&*txt, b"hello world");
+    }
+
+    #[test]
+    fn test_decompress_xz_trailing_garbage() {
+        for garbage in [&b"\x00\x00\x00"[..], b"garbage!", b"YZ"] {
+            let bytes = [&xz(b"hello world"), garbage].concat();
+            assert!(decompress(&bytes, &Limits::DEFAULT).is_err());
+        }
+    }
+
+    #[test]
+    fn test_decompress_zstd_multi_frame() {
+        let mut skippable = vec![0x50, 0x2a, 0x4d, 0x18, 0x04, 0x00, 0x00, 0x00];
+        skippable.extend(b"skip");
+        let bytes = [zstd(b"hello "), skippable, zstd(b"world")].concat();
+        let txt = decompress(&bytes, &Limits::DEFAULT).unwrap();
         assert_eq!(&*txt, b"hello world");
     }
+
+    #[test]
+    fn test_decompress_zstd_trailing_garbage() {
+        let bytes = [&zstd(b"hello world"), &b"garbage!"[..]].concat();
+        assert!(decompress(&bytes, &Limits::DEFAULT).is_err());
+    }
+
+    #[test]
+    fn test_decompress_lz4_multi_frame() {
+        let lz4 = |data: &[u8]| {
+            let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+            io::copy(&mut &data[..], &mut encoder).unwrap();
+            encoder.finish().unwrap()
+        };
+        let bytes = [lz4(b"hello "), lz4(b"world")].concat();
+        let txt = decompress(&bytes, &Limits::DEFAULT).unwrap();
+        assert_eq!(&*txt, b"hello world");
+
+        let bytes = [&lz4(b"hello world"), &b"garbage!"[..]].concat();
+        assert!(decompress(&bytes, &Limits::DEFAULT).is_err());
+    }
+
+    #[test]
+    fn test_hashing_reader() {
+        let data = (0..CHUNK_SIZE * 3 + 17)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        let padded = [data.clone(), b"hello world".to_vec()].concat();
+        for (bytes, expected) in [
+            (data.clone(), &data),
+            (gzip(&data), &data),
+            (
+                [xz(&data), vec![0; 8], xz(b"hello world"), vec![0; 4]].concat(),
+                &padded,
+            ),
+        ] {
+            let hashing = HashingReader::new(io::Cursor::new(bytes.clone()));
+            let mut reader = reader(BufReader::new(hashing.clone()), &Limits::DEFAULT).unwrap();
+            let mut decompressed = Vec::new();
+            reader.read_to_end(&mut decompressed).unwrap();
+            assert_eq!(&decompressed, expected);
+
+            let (sha256, size) = hashing.finish().unwrap();
+            assert_eq!(sha256, chksums::sha256(&bytes));
+            assert_eq!(size, bytes.len() as u64);
+        }
+    }
+
+    #[test]
+    fn test_hashing_reader_no_rereads() {
+        let data = vec![0x42; CHUNK_SIZE * 2];
+        let mut hashing = HashingReader::new(io::Cursor::new(data.clone()));
+        let mut buf = vec![0; CHUNK_SIZE * 2];
+        hashing.read_exact(&mut buf).unwrap();
+
+        // the start is kept for format detection
+        hashing.seek(SeekFrom::Start(0)).unwrap();
+        hashing.read_exact(&mut buf[..CHUNK_SIZE]).unwrap();
+        assert!(hashing.read(&mut buf).is_err());
+
+        let (sha256, _) = hashing.finish().unwrap();
+        assert_eq!(sha256, chksums::sha256(&data));
+        assert!(hashing.read(&mut buf).is_err());
+    }
 }
@@ -0,0 +1,175 @@
+//! Parse the machine-readable `debian/copyright` file (DEP-5) of a Debian source package
+use crate::compression::{self, Limits};
+use crate::errors::*;
+use crate::shell::Glob;
+use std::io::Read;
+
+/// The path of the copyright file inside of a `.debian.tar.*`
+const COPYRIGHT_PATH: &str = "debian/copyright";
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Copyright {
+    /// Glob patterns of files that have been removed when repacking the upstream tarball
+    pub files_excluded: Vec<String>,
+}
+
+impl Copyright {
+    /// Parse the header paragraph, the file paragraphs are not needed
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let text = std::str::from_utf8(bytes).context("debian/copyright is not valid utf-8")?;
+
+        let mut copyright = Copyright::default();
+        let mut field = None;
+        for line in text.lines() {
+            if line.trim().is_empty() {
+                // end of the header paragraph
+                break;
+            }
+            if line.starts_with('#') {
+                continue;
+            }
+
+            let value = if let Some(value) = line.strip_prefix([' ', '\t']) {
+                // continuation of the previous field
+                value
+            } else {
+                let (key, value) = line
+                    .split_once(':')
+                    .with_context(|| anyhow!("Invalid line in debian/copyright: {line:?}"))?;
+                field = Some(key.trim());
+                value
+            };
+
+            // Files-Excluded-<component> is used for additional tarballs
+            if field.is_some_and(|field| field.eq_ignore_ascii_case("Files-Excluded")) {
+                copyright
+                    .files_excluded
+                    .extend(value.split_whitespace().map(String::from));
+            }
+        }
+        Ok(copyright)
+    }
+
+    /// Read `debian/copyright` from a `.debian.tar.*`
+    pub fn from_debian_tarball(bytes: &[u8], limits: &Limits) -> Result<Self> {
+        let reader = compression::decoder(bytes, limits)?;
+        let mut tar = tar::Archive::new(reader);
+        for (num, entry) in (1..).zip(tar.entries()?) {
+            limits.check_entries(num)?;
+            let mut entry = entry?;
+            let path = entry.path()?;
+            if path.to_str().map(|p| p.strip_prefix("./").unwrap_or(p)) != Some(COPYRIGHT_PATH) {
+                trace!("Skipping entry in debian tarball: {path:?}");
+                continue;
+            }
+
+            limits.check_memory(entry.size())?;
+            let mut buf = Vec::new();
+            entry.read_to_end(&mut buf)?;
+            return Self::parse(&buf);
+        }
+        bail!("Failed to find {COPYRIGHT_PATH:?} in debian tarball")
+    }
+
+    /// Check if a path of the upstream tarball, including its top-level directory, has been excluded
+    ///
+    /// Like `mk-origtargz`, a pattern may match the path with or without its first component,
+    /// and a pattern that matches a directory also excludes everything inside of it.
+    pub fn is_excluded(&self, path: &str) -> bool {
+        let mut candidates = vec![path.chars().collect::<Vec<_>>()];
+        if let Some((_, rest)) = path.split_once('/') {
+            candidates.push(rest.chars().collect());
+        }
+
+        self.files_excluded.iter().any(|pattern| {
+            let pattern = pattern.trim_start_matches("./").trim_end_matches('/');
+            let glob = Glob::new(pattern);
+            candidates.iter().any(|path| {
+                glob.matches(path)
+                    || (0..path.len())
+                        .filter(|idx| path[*idx] == '/')
+                        .any(|idx| glob.matches(&path[..idx]))
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COPYRIGHT: &str =
+        "Format: https://www.debian.org/doc/packaging-manuals/copyright-format/1.0/
+Upstream-Name: foo
+Source: https://example.com/foo
+Files-Excluded: vendor
+ docs/*.pdf
+    */.gitignore
+Files-Excluded-extra: ignored
+Comment: The vendored dependencies are packaged separately
+
+Files: *
+Copyright: 2024 Someone
+License: MIT
+Files-Excluded: not-in-header
+";
+
+    #[test]
+    fn test_parse_files_excluded() {
+        let copyright = Copyright::parse(COPYRIGHT.as_bytes()).unwrap();
+        assert_eq!(
+            copyright,
+            Copyright {
+                files_excluded: vec![
+                    "vendor".to_string(),
+                    "docs/*.pdf".to_string(),
+                    "*/.gitignore".to_string(),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_is_excluded() {
+        let copyright = Copyright::parse(COPYRIGHT.as_bytes()).unwrap();
+        assert!(copyright.is_excluded("foo-1.0/vendor"));
+        assert!(copyright.is_excluded("foo-1.0/vendor/libfoo/lib.rs"));
+        assert!(copyright.is_excluded("foo-1.0/docs/manual.pdf"));
+        assert!(copyright.is_excluded("foo-1.0/docs/api/manual.pdf"));
+        assert!(copyright.is_excluded("foo-1.0/src/.gitignore"));
+        // `*` also matches the top-level directory
+        assert!(copyright.is_excluded("foo-1.0/.gitignore"));
+        assert!(!copyright.is_excluded("foo-1.0/vendored.txt"));
+        assert!(!copyright.is_excluded("foo-1.0/docs/manual.md"));
+        assert!(!copyright.is_excluded("foo-1.0/src/vendor"));
+    }
+
+    #[test]
+    fn test_is_excluded_without_top_level() {
+        let copyright = Copyright::parse(COPYRIGHT.as_bytes()).unwrap();
+        assert!(copyright.is_excluded("vendor/libfoo/lib.rs"));
+        assert!(copyright.is_excluded("src/.gitignore"));
+        assert!(!copyright.is_excluded("vendored.txt"));
+    }
+
+    #[test]
+    fn test_from_debian_tarball() {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, content) in [
+            ("debian/rules", &b"#!/usr/bin/make -f\n"[..]),
+            ("debian/copyright", COPYRIGHT.as_bytes()),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(0o644);
+            header.set_size(content.len() as u64);
+            builder.append_data(&mut header, path, content).unwrap();
+        }
+        let tarball = builder.into_inner().unwrap();
+
+        let copyright = Copyright::from_debian_tarball(&tarball, &Limits::DEFAULT).unwrap();
+        assert_eq!(copyright.files_excluded.len(), 3);
+
+        let err = Copyright::from_debian_tarball(&tarball[..1024], &Limits::DEFAULT);
+        assert!(err.is_err());
+    }
+}
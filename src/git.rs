@@ -0,0 +1,283 @@
+//! Read signed tags from a local git repository, using the `git` command
+
+use crate::errors::*;
+use crate::pgp;
+use sequoia_openpgp::Fingerprint;
+use std::io::Write;
+use std::path::Path;
+use std::process::Stdio;
+use std::str;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+const PGP_SIGNATURE: &str = "-----BEGIN PGP SIGNATURE-----";
+const SSH_SIGNATURE: &str = "-----BEGIN SSH SIGNATURE-----";
+const X509_SIGNATURE: &str = "-----BEGIN SIGNED MESSAGE-----";
+
+/// The tag Arch Linux uses for a package version (e.g. `1:2.0-3` is tagged as `1-2.0-3`)
+pub fn archlinux_tag_name(version: &str) -> String {
+    version.replace(':', "-")
+}
+
+async fn git(repo: &Path, args: &[&str]) -> Result<Vec<u8>> {
+    debug!("Executing git {args:?} in {repo:?}");
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .context("Failed to execute git")?;
+    if !output.status.success() {
+        bail!(
+            "git {args:?} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TagSignature {
+    Pgp(Vec<u8>),
+    Ssh(Vec<u8>),
+}
+
+/// An annotated tag object
+#[derive(Debug, PartialEq)]
+pub struct Tag {
+    pub object: String,
+    pub kind: String,
+    pub name: String,
+    /// The part of the tag object covered by the signature
+    pub payload: Vec<u8>,
+    pub signature: Option<TagSignature>,
+}
+
+impl Tag {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let text = str::from_utf8(bytes).context("Tag object is not valid utf-8")?;
+
+        // the signature is appended to the tag message, like git we split at the last line
+        // starting a signature, the message itself may quote a marker
+        let mut signature = None;
+        let mut idx = 0;
+        for line in text.split_inclusive('\n') {
+            if let Some(marker) = [PGP_SIGNATURE, SSH_SIGNATURE, X509_SIGNATURE]
+                .into_iter()
+                .find(|marker| line.starts_with(marker))
+            {
+                signature = Some((marker, idx));
+            }
+            idx += line.len();
+        }
+        let (payload, signature) = match signature {
+            Some((marker, idx)) => {
+                let (payload, sig) = text.split_at(idx);
+                let sig = sig.as_bytes().to_vec();
+                let sig = match marker {
+                    PGP_SIGNATURE => TagSignature::Pgp(sig),
+                    SSH_SIGNATURE => TagSignature::Ssh(sig),
+                    _ => bail!("X.509 signed tags are not supported"),
+                };
+                (payload, Some(sig))
+            }
+            None => (text, None),
+        };
+
+        let mut object = None;
+        let mut kind = None;
+        let mut name = None;
+        for line in payload.lines() {
+            if line.is_empty() {
+                break;
+            }
+            let (key, value) = line
+                .split_once(' ')
+                .with_context(|| anyhow!("Malformed header in tag object: {line:?}"))?;
+            let field = match key {
+                "object" => &mut object,
+                "type" => &mut kind,
+                "tag" => &mut name,
+                _ => continue,
+            };
+            if field.replace(value.to_string()).is_some() {
+                bail!("Duplicate header in tag object: {key:?}");
+            }
+        }
+
+        let object: String = object.context("Missing object in tag object")?;
+        if object.is_empty() || !object.chars().all(|c| c.is_ascii_hexdigit()) {
+            bail!("Invalid object id in tag object: {object:?}");
+        }
+
+        Ok(Tag {
+            object,
+            kind: kind.context("Missing type in tag object")?,
+            name: name.context("Missing tag name in tag object")?,
+            payload: payload.as_bytes().to_vec(),
+            signature,
+        })
+    }
+
+    /// Verify an OpenPGP signed tag, returns the fingerprint of the signing key
+    pub fn verify_pgp(&self, keyring: &[pgp::SigningKey]) -> Result<Fingerprint> {
+        let Some(TagSignature::Pgp(sig)) = &self.signature else {
+            bail!("Tag {:?} has no OpenPGP signature", self.name);
+        };
+        let sig = pgp::signature(sig)?;
+        pgp::verify(keyring, &sig, &self.payload)
+    }
+
+    /// Verify an SSH signed tag with `ssh-keygen`, returns the principal of the signing key
+    pub async fn verify_ssh(&self, allowed_signers: &Path) -> Result<String> {
+        let Some(TagSignature::Ssh(sig)) = &self.signature else {
+            bail!("Tag {:?} has no SSH signature", self.name);
+        };
+
+        let mut sig_file = tempfile::NamedTempFile::new()?;
+        sig_file.write_all(sig)?;
+        sig_file.flush()?;
+
+        let output = Command::new("ssh-keygen")
+            .args(["-Y", "find-principals", "-f"])
+            .arg(allowed_signers)
+            .arg("-s")
+            .arg(sig_file.path())
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .context("Failed to execute ssh-keygen")?;
+        if !output.status.success() {
+            bail!("Failed to find any allowed signers for SSH signature");
+        }
+        let principals = String::from_utf8(output.stdout)?;
+
+        for principal in principals.lines() {
+            debug!("Attempting verification for principal {principal:?}");
+            let mut child = Command::new("ssh-keygen")
+                .args(["-Y", "verify", "-n", "git", "-f"])
+                .arg(allowed_signers)
+                .arg("-I")
+                .arg(principal)
+                .arg("-s")
+                .arg(sig_file.path())
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .context("Failed to execute ssh-keygen")?;
+
+            let mut stdin = child.stdin.take().context("Failed to open stdin")?;
+            stdin.write_all(&self.payload).await?;
+            drop(stdin);
+
+            let output = child.wait_with_output().await?;
+            if output.status.success() {
+                debug!("Successfully verified signature");
+                return Ok(principal.to_string());
+            }
+            debug!(
+                "Signature verification failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        bail!("Failed to verify SSH signature of tag {:?}", self.name)
+    }
+}
+
+/// Read an annotated tag from the repository
+pub async fn read_tag(repo: &Path, name: &str) -> Result<Tag> {
+    let refname = format!("refs/tags/{name}");
+    let bytes = git(repo, &["cat-file", "tag", &refname]).await?;
+    let tag = Tag::parse(&bytes)?;
+
+    // the ref could point to a tag object that was created for a different version
+    if tag.name != name {
+        bail!(
+            "Tag object has unexpected name, expected={name:?}, found={:?}",
+            tag.name
+        );
+    }
+    if tag.kind != "commit" {
+        bail!("Tag {name:?} does not point to a commit: {:?}", tag.kind);
+    }
+
+    Ok(tag)
+}
+
+/// Read a file from a commit of the repository
+pub async fn read_file(repo: &Path, commit: &str, path: &str) -> Result<Vec<u8>> {
+    let object = format!("{commit}:{path}");
+    git(repo, &["cat-file", "blob", &object]).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TAG: &str = "object 4c6b7e8d3dbf1f3a8a28e4f31c2e0d1b5a9a8c71
+type commit
+tag 2.0-3
+tagger kpcyrd <kpcyrd@archlinux.org> 1686262344 +0200
+
+2.0-3
+-----BEGIN PGP SIGNATURE-----
+
+iQIzBAABCAAdFiEEZLE/cRfW4H1mG7zg/nY6ZPXlT9YFAmSCXxgACgkQ/nY6ZPXl
+=AAAA
+-----END PGP SIGNATURE-----
+";
+
+    #[test]
+    fn test_parse_tag() {
+        let tag = Tag::parse(TAG.as_bytes()).unwrap();
+        assert_eq!(tag.object, "4c6b7e8d3dbf1f3a8a28e4f31c2e0d1b5a9a8c71");
+        assert_eq!(tag.kind, "commit");
+        assert_eq!(tag.name, "2.0-3");
+        assert_eq!(
+            tag.payload,
+            b"object 4c6b7e8d3dbf1f3a8a28e4f31c2e0d1b5a9a8c71
+type commit
+tag 2.0-3
+tagger kpcyrd <kpcyrd@archlinux.org> 1686262344 +0200
+
+2.0-3
+"
+        );
+        assert!(matches!(tag.signature, Some(TagSignature::Pgp(_))));
+    }
+
+    #[test]
+    fn test_parse_tag_quoted_marker() {
+        let quoted = TAG.replace(
+            "\n2.0-3\n",
+            "\n2.0-3\n\nExample of a signature block:\n-----BEGIN PGP SIGNATURE-----\n-----END PGP SIGNATURE-----\n",
+        );
+        let tag = Tag::parse(quoted.as_bytes()).unwrap();
+        let payload = str::from_utf8(&tag.payload).unwrap();
+        assert!(payload.ends_with("Example of a signature block:\n-----BEGIN PGP SIGNATURE-----\n-----END PGP SIGNATURE-----\n"));
+        assert_eq!(
+            tag.signature,
+            Some(TagSignature::Pgp(
+                TAG.as_bytes()[TAG.find("-----BEGIN").unwrap()..].to_vec()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_tag_unsigned() {
+        let tag = Tag::parse(b"object 4c6b7e8d\ntype commit\ntag 2.0-3\n\nmessage\n").unwrap();
+        assert_eq!(tag.signature, None);
+
+        assert!(Tag::parse(b"object --output=foo\ntype commit\ntag 2.0-3\n\n").is_err());
+    }
+
+    #[test]
+    fn test_archlinux_tag_name() {
+        assert_eq!(archlinux_tag_name("2.0-3"), "2.0-3");
+        assert_eq!(archlinux_tag_name("1:2.0-3"), "1-2.0-3");
+    }
+}
@@ -7,7 +7,16 @@ pub mod args;
 pub mod buildinfo;
 pub mod chksums;
 pub mod compression;
+pub mod copyright;
 pub mod errors;
+pub mod git;
+pub mod mirror;
+pub mod mtree;
 pub mod pgp;
 pub mod pkgbuild;
+pub mod pkginfo;
 pub mod plumbing;
+pub mod shell;
+pub mod srcinfo;
+pub mod syncdb;
+pub mod tree;
@@ -1,4 +1,5 @@
 use backseat_signed::args::{Args, SubCommand};
+use backseat_signed::compression::Limits;
 use backseat_signed::errors::*;
 use backseat_signed::plumbing;
 use clap::Parser;
@@ -20,12 +21,14 @@ async fn main() -> Result<()> {
     };
     env_logger::init_from_env(Env::default().default_filter_or(log_level));
 
+    let limits = Limits::from(&args.limits);
+
     match args.subcommand {
         /*
         SubCommand::Create(_create) => bail!("This feature doesn't exist yet, refer to the README for how to use the plumbing commands"),
         SubCommand::Verify(_verify) => bail!("This feature doesn't exist yet, refer to the README for how to use the plumbing commands"),
         */
-        SubCommand::Plumbing(plumbing) => plumbing::run(plumbing).await,
+        SubCommand::Plumbing(plumbing) => plumbing::run(plumbing, &limits).await,
         SubCommand::Completions(completions) => completions.generate(io::stdout()),
     }
 }
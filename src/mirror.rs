@@ -0,0 +1,362 @@
+use crate::apt;
+use crate::chksums;
+use crate::compression::{self, Limits};
+use crate::errors::*;
+use crate::pgp;
+use apt_parser::release::ReleaseHash;
+use std::path::{Component, Path, PathBuf};
+use tokio::fs;
+
+/// A file located in a mirror, verified with a checksum entry of a signed index
+#[derive(Debug)]
+pub struct MirrorFile<'a> {
+    pub path: PathBuf,
+    pub bytes: Vec<u8>,
+    pub entry: &'a ReleaseHash,
+}
+
+/// A tarball in `pool/`, verified with its checksum entry without loading it into memory
+#[derive(Debug)]
+pub struct PoolFile<'a> {
+    pub path: PathBuf,
+    pub entry: &'a ReleaseHash,
+    /// The sha256 and size of the decompressed content
+    pub decompressed: (String, u64),
+}
+
+/// Make sure a path taken from an index can't point outside of the mirror
+fn ensure_relative(path: &str) -> Result<&Path> {
+    let path = Path::new(path);
+    if !path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        bail!("Refusing to access path outside of mirror: {path:?}");
+    }
+    Ok(path)
+}
+
+async fn read_verified(path: PathBuf, entry: &ReleaseHash) -> Result<MirrorFile<'_>> {
+    let bytes = fs::read(&path)
+        .await
+        .with_context(|| anyhow!("Failed to read {path:?}"))?;
+
+    let sha256 = chksums::sha256(&bytes);
+    if sha256 != entry.hash || bytes.len() as u64 != entry.size {
+        bail!(
+            "File {path:?} does not match checksum entry for {:?}",
+            entry.filename
+        );
+    }
+
+    Ok(MirrorFile { path, bytes, entry })
+}
+
+async fn hash_verified<'a>(
+    path: PathBuf,
+    entry: &'a ReleaseHash,
+    limits: &Limits,
+) -> Result<PoolFile<'a>> {
+    let ((sha256, size), decompressed) =
+        compression::sha256_file_and_decompressed(&path, limits).await?;
+    if sha256 != entry.hash || size != entry.size {
+        bail!(
+            "File {path:?} does not match checksum entry for {:?}",
+            entry.filename
+        );
+    }
+
+    Ok(PoolFile {
+        path,
+        entry,
+        decompressed,
+    })
+}
+
+/// A local Debian mirror, laid out with `dists/` and `pool/` directories
+#[derive(Debug)]
+pub struct DebianMirror {
+    root: PathBuf,
+}
+
+impl DebianMirror {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        DebianMirror { root: root.into() }
+    }
+
+    pub fn suite_path(&self, suite: &str) -> PathBuf {
+        self.root.join("dists").join(suite)
+    }
+
+    /// Load and verify the release file of a suite
+    ///
+    /// `InRelease` is preferred, `Release` with `Release.gpg` is used if it doesn't exist.
+    pub async fn load_release(
+        &self,
+        suite: &str,
+        keyring: &[pgp::SigningKey],
+    ) -> Result<apt::Release> {
+        let suite_path = self.suite_path(suite);
+
+        let path = suite_path.join("InRelease");
+        match fs::read(&path).await {
+            Ok(bytes) => {
+                info!("Verifying signed release file from {path:?}");
+                let (_signer, release) = pgp::verify_cleartext(keyring, &bytes)?;
+                return apt::Release::parse(&release);
+            }
+            Err(err) => {
+                debug!("Failed to read {path:?}, trying Release and Release.gpg instead: {err:#}")
+            }
+        }
+
+        let path = suite_path.join("Release.gpg");
+        info!("Loading signature from {path:?}");
+        let sig = fs::read(&path)
+            .await
+            .with_context(|| anyhow!("Failed to load signatures from {path:?}"))?;
+        let sig = pgp::signature(&sig)?;
+
+        let path = suite_path.join("Release");
+        info!("Loading release file from {path:?}");
+        let release = fs::read(&path)
+            .await
+            .with_context(|| anyhow!("Failed to load release file from {path:?}"))?;
+
+        pgp::verify(keyring, &sig, &release)?;
+        apt::Release::parse(&release)
+    }
+
+    /// Locate an index (like `main/source/Sources`) listed in the release file
+    ///
+    /// All compression variants are tried, both by their canonical filename and their
+    /// `Acquire-By-Hash` path, so rotated or partially synced mirrors still resolve.
+    pub async fn resolve_index<'a>(
+        &self,
+        suite: &str,
+        release: &'a apt::Release,
+        path: &str,
+    ) -> Result<MirrorFile<'a>> {
+        let path = apt::strip_index_variant(path);
+        let dir = self.suite_path(suite);
+
+        for suffix in apt::INDEX_VARIANTS {
+            let variant = format!("{path}{suffix}");
+            let entry = match release.find_entry_by_path(&variant) {
+                Ok(entry) => entry,
+                Err(err) => {
+                    debug!("Skipping index variant: {err:#}");
+                    continue;
+                }
+            };
+
+            let by_hash = apt::by_hash_path(&entry.filename, &entry.hash);
+            for candidate in [&entry.filename, &by_hash] {
+                let candidate = dir.join(ensure_relative(candidate)?);
+                match read_verified(candidate, entry).await {
+                    Ok(file) => {
+                        info!("Found {:?} at {:?}", entry.filename, file.path);
+                        return Ok(file);
+                    }
+                    Err(err) => debug!("Skipping index candidate: {err:#}"),
+                }
+            }
+        }
+
+        bail!("Failed to locate {path:?} in mirror at {dir:?}")
+    }
+
+    /// Locate and verify the .orig tarballs of a source package in `pool/`, using its `Directory` field
+    ///
+    /// Each file is read only once, to verify it and to hash its decompressed content.
+    pub async fn resolve_orig_tarballs<'a>(
+        &self,
+        pkg: &'a apt::SourcePkg,
+        limits: &Limits,
+    ) -> Result<Vec<PoolFile<'a>>> {
+        let directory = pkg
+            .directory
+            .as_deref()
+            .with_context(|| anyhow!("Source package {:?} has no Directory field", pkg.package))?;
+        let directory = self.root.join(ensure_relative(directory)?);
+
+        let mut files = Vec::new();
+        for entry in &pkg.checksums_sha256 {
+            if !apt::is_orig_tarball(&entry.filename) {
+                continue;
+            }
+            let path = directory.join(ensure_relative(&entry.filename)?);
+            debug!("Decompressing {path:?} from pool");
+            let file = hash_verified(path, entry, limits).await?;
+            info!("Verified {:?}", file.path);
+            files.push(file);
+        }
+
+        Ok(files)
+    }
+}
+
+/// A package located in an Arch Linux mirror, along with its detached signature
+#[derive(Debug, PartialEq)]
+pub struct ArchPackage {
+    pub path: PathBuf,
+    pub sig: PathBuf,
+}
+
+/// Check if a filename is a package of the given name and version (`pkgver-pkgrel`)
+fn is_archlinux_pkg(filename: &str, pkgname: &str, version: &str, arch: Option<&str>) -> bool {
+    let Some(remaining) = filename
+        .strip_prefix(pkgname)
+        .and_then(|s| s.strip_prefix('-'))
+        .and_then(|s| s.strip_prefix(version))
+        .and_then(|s| s.strip_prefix('-'))
+    else {
+        return false;
+    };
+    let Some((pkgarch, ext)) = remaining.split_once(".pkg.tar") else {
+        return false;
+    };
+    if pkgarch.contains('-') || ext.ends_with(".sig") {
+        return false;
+    }
+    arch.is_none_or(|arch| pkgarch == arch || pkgarch == "any")
+}
+
+/// A local Arch Linux mirror or archive
+///
+/// Both the layout of archive.archlinux.org (`packages/c/cmatrix/`) and of
+/// pacman mirrors (`core/os/x86_64/`) are supported.
+#[derive(Debug)]
+pub struct ArchMirror {
+    root: PathBuf,
+}
+
+impl ArchMirror {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        ArchMirror { root: root.into() }
+    }
+
+    async fn search_directories(&self, pkgname: &str) -> Result<Vec<PathBuf>> {
+        let mut dirs = Vec::new();
+
+        if let Some(first) = pkgname.chars().next() {
+            dirs.push(
+                self.root
+                    .join("packages")
+                    .join(first.to_string())
+                    .join(pkgname),
+            );
+        }
+
+        if let Ok(mut repos) = fs::read_dir(&self.root).await {
+            while let Some(repo) = repos.next_entry().await? {
+                let os = repo.path().join("os");
+                let Ok(mut arches) = fs::read_dir(&os).await else {
+                    continue;
+                };
+                while let Some(arch) = arches.next_entry().await? {
+                    dirs.push(arch.path());
+                }
+            }
+        }
+
+        dirs.push(self.root.clone());
+        Ok(dirs)
+    }
+
+    /// Locate a package by name and version (`pkgver-pkgrel`), the signature is expected next to it
+    pub async fn find_package(
+        &self,
+        pkgname: &str,
+        version: &str,
+        arch: Option<&str>,
+    ) -> Result<ArchPackage> {
+        ensure_relative(pkgname)?;
+
+        let mut found = Vec::new();
+        for dir in self.search_directories(pkgname).await? {
+            trace!("Searching for package in {dir:?}");
+            let Ok(mut entries) = fs::read_dir(&dir).await else {
+                continue;
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let filename = entry.file_name();
+                let Some(filename) = filename.to_str() else {
+                    continue;
+                };
+                if is_archlinux_pkg(filename, pkgname, version, arch) {
+                    debug!("Found package in mirror: {:?}", entry.path());
+                    found.push(entry.path());
+                }
+            }
+            if !found.is_empty() {
+                break;
+            }
+        }
+
+        let path = match found.as_slice() {
+            [] => bail!("Failed to locate package {pkgname:?} (version={version:?}) in mirror at {:?}", self.root),
+            [path] => path.clone(),
+            _ => bail!("Found multiple packages for {pkgname:?} (version={version:?}), please specify an architecture: {found:?}"),
+        };
+
+        let mut sig = path.clone().into_os_string();
+        sig.push(".sig");
+        let sig = PathBuf::from(sig);
+
+        info!("Found package at {path:?}");
+        Ok(ArchPackage { path, sig })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ensure_relative() {
+        assert!(ensure_relative("pool/main/c/cmatrix").is_ok());
+        assert!(ensure_relative("cmatrix_2.0.orig.tar.gz").is_ok());
+        assert!(ensure_relative("../etc/passwd").is_err());
+        assert!(ensure_relative("pool/../../etc").is_err());
+        assert!(ensure_relative("/etc/passwd").is_err());
+    }
+
+    #[test]
+    fn test_is_archlinux_pkg() {
+        let filename = "cmatrix-2.0-3-x86_64.pkg.tar.zst";
+        assert!(is_archlinux_pkg(filename, "cmatrix", "2.0-3", None));
+        assert!(is_archlinux_pkg(
+            filename,
+            "cmatrix",
+            "2.0-3",
+            Some("x86_64")
+        ));
+        assert!(!is_archlinux_pkg(
+            filename,
+            "cmatrix",
+            "2.0-3",
+            Some("aarch64")
+        ));
+        assert!(!is_archlinux_pkg(filename, "cmatrix", "2.0-2", None));
+        assert!(!is_archlinux_pkg(filename, "cmatri", "x-2.0-3", None));
+        assert!(!is_archlinux_pkg(
+            "cmatrix-2.0-3-x86_64.pkg.tar.zst.sig",
+            "cmatrix",
+            "2.0-3",
+            None
+        ));
+        assert!(is_archlinux_pkg(
+            "python-six-1.16.0-8-any.pkg.tar.zst",
+            "python-six",
+            "1.16.0-8",
+            Some("x86_64")
+        ));
+        assert!(!is_archlinux_pkg(
+            "python-six-doc-1.16.0-8-any.pkg.tar.zst",
+            "python-six",
+            "1.16.0-8",
+            None
+        ));
+    }
+}
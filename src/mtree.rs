@@ -0,0 +1,380 @@
+use crate::chksums;
+use crate::compression::{self, Limits};
+use crate::errors::*;
+use bstr::ByteSlice;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::str;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EntryType {
+    File,
+    Dir,
+    Link,
+}
+
+impl EntryType {
+    fn parse(value: &str) -> Result<Self> {
+        match value {
+            "file" => Ok(EntryType::File),
+            "dir" => Ok(EntryType::Dir),
+            "link" => Ok(EntryType::Link),
+            _ => bail!("Unsupported entry type in .MTREE: {value:?}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MtreeEntry {
+    pub path: String,
+    pub kind: EntryType,
+    pub mode: Option<u32>,
+    pub size: Option<u64>,
+    pub sha256digest: Option<String>,
+    pub link: Option<String>,
+}
+
+/// Decode the escape sequences used for paths in mtree files (e.g. `\040` for a space)
+fn unescape(value: &str) -> Result<String> {
+    let mut out = Vec::new();
+    let mut bytes = value.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            out.push(b);
+            continue;
+        }
+
+        let digits = bytes.clone().take(3).collect::<Vec<_>>();
+        if digits.first() == Some(&b'\\') {
+            bytes.next();
+            out.push(b'\\');
+            continue;
+        }
+
+        if digits.len() != 3 || !digits.iter().all(|d| (b'0'..=b'7').contains(d)) {
+            bail!("Unsupported escape sequence in .MTREE: {value:?}");
+        }
+        bytes.nth(2);
+
+        let value = digits
+            .iter()
+            .fold(0u32, |acc, d| acc * 8 + u32::from(d - b'0'));
+        let value = u8::try_from(value)
+            .with_context(|| anyhow!("Invalid escape sequence in .MTREE: {value:?}"))?;
+        out.push(value);
+    }
+    let out = String::from_utf8(out).context("Path in .MTREE is not valid utf-8")?;
+    Ok(out)
+}
+
+/// Strip the `./` prefix and trailing slashes so paths in .MTREE and tar can be compared
+fn normalize_path(path: &str) -> &str {
+    let path = path.strip_prefix("./").unwrap_or(path);
+    path.trim_end_matches('/')
+}
+
+/// Parse a (gzip compressed) .MTREE file
+pub fn parse(bytes: &[u8], limits: &Limits) -> Result<Vec<MtreeEntry>> {
+    let bytes = compression::decompress(bytes, limits).context("Failed to decompress .MTREE")?;
+
+    let mut defaults = HashMap::<String, String>::new();
+    let mut entries = Vec::new();
+    for line in bytes.lines() {
+        trace!("Line in .MTREE: {:?}", bstr::BStr::new(line));
+        let line = str::from_utf8(line)?;
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut words = line.split(' ').filter(|word| !word.is_empty());
+        let Some(first) = words.next() else {
+            continue;
+        };
+
+        match first {
+            "/set" => {
+                for word in words {
+                    let (key, value) = word
+                        .split_once('=')
+                        .with_context(|| anyhow!("Malformed keyword in .MTREE: {word:?}"))?;
+                    defaults.insert(key.to_string(), value.to_string());
+                }
+            }
+            "/unset" => {
+                for key in words {
+                    if key == "all" {
+                        defaults.clear();
+                    } else {
+                        defaults.remove(key);
+                    }
+                }
+            }
+            path if path.starts_with('/') => {
+                bail!("Unsupported directive in .MTREE: {path:?}");
+            }
+            path => {
+                let mut keywords = defaults.clone();
+                for word in words {
+                    let (key, value) = word
+                        .split_once('=')
+                        .with_context(|| anyhow!("Malformed keyword in .MTREE: {word:?}"))?;
+                    keywords.insert(key.to_string(), value.to_string());
+                }
+
+                let path = unescape(path)?;
+                let kind = keywords
+                    .get("type")
+                    .with_context(|| anyhow!("Missing type for {path:?} in .MTREE"))?;
+                let kind = EntryType::parse(kind)?;
+                let mode = keywords
+                    .get("mode")
+                    .map(|mode| u32::from_str_radix(mode, 8))
+                    .transpose()
+                    .with_context(|| anyhow!("Invalid mode for {path:?} in .MTREE"))?;
+                let size = keywords
+                    .get("size")
+                    .map(|size| size.parse())
+                    .transpose()
+                    .with_context(|| anyhow!("Invalid size for {path:?} in .MTREE"))?;
+                let link = keywords
+                    .get("link")
+                    .map(|link| unescape(link))
+                    .transpose()?;
+
+                entries.push(MtreeEntry {
+                    path: normalize_path(&path).to_string(),
+                    kind,
+                    mode,
+                    size,
+                    sha256digest: keywords.get("sha256digest").cloned(),
+                    link,
+                });
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// The properties of a file observed in the tar stream of a package
+#[derive(Debug)]
+struct Observed {
+    kind: EntryType,
+    mode: u32,
+    size: u64,
+    sha256: Option<String>,
+    link: Option<String>,
+}
+
+fn compare(entry: &MtreeEntry, observed: &Observed) -> Result<()> {
+    let path = &entry.path;
+    if entry.kind != observed.kind {
+        bail!(
+            "Type mismatch for {path:?}: .MTREE={:?}, package={:?}",
+            entry.kind,
+            observed.kind
+        );
+    }
+    if let Some(mode) = entry.mode {
+        if mode != observed.mode {
+            bail!(
+                "Mode mismatch for {path:?}: .MTREE={mode:o}, package={:o}",
+                observed.mode
+            );
+        }
+    }
+    if entry.kind == EntryType::File {
+        if let Some(size) = entry.size {
+            if size != observed.size {
+                bail!(
+                    "Size mismatch for {path:?}: .MTREE={size}, package={}",
+                    observed.size
+                );
+            }
+        }
+        let sha256 = entry
+            .sha256digest
+            .as_ref()
+            .with_context(|| anyhow!("Missing sha256digest for {path:?} in .MTREE"))?;
+        if Some(sha256) != observed.sha256.as_ref() {
+            bail!(
+                "Digest mismatch for {path:?}: .MTREE={sha256:?}, package={:?}",
+                observed.sha256
+            );
+        }
+    }
+    if entry.link != observed.link {
+        bail!(
+            "Link target mismatch for {path:?}: .MTREE={:?}, package={:?}",
+            entry.link,
+            observed.link
+        );
+    }
+    Ok(())
+}
+
+/// Verify all files in an Arch Linux package match the digests, sizes, modes and link targets in .MTREE
+///
+/// Returns the number of verified entries.
+pub fn verify_archlinux_pkg(bytes: &[u8], limits: &Limits) -> Result<usize> {
+    let decoder = compression::decoder(bytes, limits)?;
+    let mut tar = tar::Archive::new(decoder);
+
+    let mut mtree = None;
+    let mut observed = BTreeMap::new();
+    for (num, entry) in (1..).zip(tar.entries()?) {
+        limits.check_entries(num)?;
+        let mut entry = entry?;
+        let path = entry.path()?;
+        let path = path
+            .to_str()
+            .with_context(|| anyhow!("Path in package is not valid utf-8: {path:?}"))?;
+        let path = normalize_path(path).to_string();
+
+        let header = entry.header();
+        let mode = header.mode()? & 0o7777;
+        let (kind, link, sha256, size) = match header.entry_type() {
+            tar::EntryType::Regular if path == ".MTREE" => {
+                debug!("Found .MTREE file in package");
+                limits.check_memory(entry.size())?;
+                let mut buf = Vec::new();
+                entry.read_to_end(&mut buf)?;
+                mtree = Some(parse(&buf, limits)?);
+                continue;
+            }
+            tar::EntryType::Regular => {
+                // files are hashed while reading, they may be too large to hold in memory
+                let (sha256, size) = chksums::sha256_reader(&mut entry)?;
+                (EntryType::File, None, Some(sha256), size)
+            }
+            tar::EntryType::Link => {
+                // hardlinks are listed as regular files in .MTREE
+                let target = entry
+                    .link_name()?
+                    .context("Hardlink in package has no target")?;
+                let target = target
+                    .to_str()
+                    .with_context(|| anyhow!("Hardlink target is not valid utf-8: {target:?}"))?;
+                let target = normalize_path(target);
+                let target: &Observed = observed.get(target).with_context(|| {
+                    anyhow!("Hardlink {path:?} points to unknown file: {target:?}")
+                })?;
+                (EntryType::File, None, target.sha256.clone(), target.size)
+            }
+            tar::EntryType::Directory => (EntryType::Dir, None, None, 0),
+            tar::EntryType::Symlink => {
+                let target = entry
+                    .link_name()?
+                    .context("Symlink in package has no target")?;
+                let target = target
+                    .to_str()
+                    .with_context(|| anyhow!("Symlink target is not valid utf-8: {target:?}"))?
+                    .to_string();
+                (EntryType::Link, Some(target), None, 0)
+            }
+            other => bail!("Unsupported entry type in package for {path:?}: {other:?}"),
+        };
+
+        trace!("Found {kind:?} in package: {path:?}");
+        let observed_entry = Observed {
+            kind,
+            mode,
+            size,
+            sha256,
+            link,
+        };
+        if observed.insert(path.clone(), observed_entry).is_some() {
+            bail!("Found duplicate entry in package: {path:?}");
+        }
+    }
+
+    let mtree = mtree.context("Failed to locate .MTREE in package")?;
+
+    for entry in &mtree {
+        let Some(found) = observed.remove(&entry.path) else {
+            bail!(
+                "File listed in .MTREE is missing from package: {:?}",
+                entry.path
+            );
+        };
+        compare(entry, &found)?;
+        debug!("Verified {:?}", entry.path);
+    }
+
+    if !observed.is_empty() {
+        let paths = observed.keys().collect::<Vec<_>>();
+        bail!("Package contains files not listed in .MTREE: {paths:?}");
+    }
+
+    Ok(mtree.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("./usr/share/a\\040b").unwrap(), "./usr/share/a b");
+        assert_eq!(unescape("a\\\\b").unwrap(), "a\\b");
+        assert!(unescape("a\\9").is_err());
+    }
+
+    #[test]
+    fn test_parse_mtree() {
+        let mtree = parse(
+            b"#mtree
+/set type=file uid=0 gid=0 mode=644
+./.BUILDINFO time=1686262344.0 size=5 md5digest=b1946ac92492d2347c6235b4d2611184 sha256digest=5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03
+./usr time=1686262344.0 mode=755 type=dir
+/set mode=755
+./usr/bin/cmatrix time=1686262344.0 size=5
+/unset size
+./usr/lib/libfoo.so time=1686262344.0 mode=777 type=link link=libfoo.so.1
+",
+            &Limits::DEFAULT,
+        )
+        .unwrap();
+        assert_eq!(
+            mtree,
+            vec![
+                MtreeEntry {
+                    path: ".BUILDINFO".to_string(),
+                    kind: EntryType::File,
+                    mode: Some(0o644),
+                    size: Some(5),
+                    sha256digest: Some(
+                        "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03"
+                            .to_string()
+                    ),
+                    link: None,
+                },
+                MtreeEntry {
+                    path: "usr".to_string(),
+                    kind: EntryType::Dir,
+                    mode: Some(0o755),
+                    size: None,
+                    sha256digest: None,
+                    link: None,
+                },
+                MtreeEntry {
+                    path: "usr/bin/cmatrix".to_string(),
+                    kind: EntryType::File,
+                    mode: Some(0o755),
+                    size: Some(5),
+                    sha256digest: None,
+                    link: None,
+                },
+                MtreeEntry {
+                    path: "usr/lib/libfoo.so".to_string(),
+                    kind: EntryType::Link,
+                    mode: Some(0o777),
+                    size: None,
+                    sha256digest: None,
+                    link: Some("libfoo.so.1".to_string()),
+                },
+            ]
+        );
+    }
+}
@@ -1,10 +1,15 @@
 use crate::errors::*;
 use sequoia_openpgp::cert::prelude::*;
 use sequoia_openpgp::packet::Signature;
+use sequoia_openpgp::parse::stream::{
+    MessageLayer, MessageStructure, VerificationHelper, VerifierBuilder,
+};
 use sequoia_openpgp::parse::{PacketParser, PacketParserResult, Parse};
+use sequoia_openpgp::policy::StandardPolicy;
 use sequoia_openpgp::types::SignatureType;
 use sequoia_openpgp::Packet;
-use sequoia_openpgp::{Cert, Fingerprint};
+use sequoia_openpgp::{Cert, Fingerprint, KeyHandle};
+use std::io::Read;
 
 pub fn keyring(bytes: &[u8]) -> Result<Vec<SigningKey>> {
     let mut keys = Vec::new();
@@ -46,6 +51,49 @@ pub struct SigningKey {
     cert: Cert,
 }
 
+impl SigningKey {
+    /// The fingerprint of the primary key
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.cert.fingerprint()
+    }
+}
+
+/// Only keep the certificates whose primary key fingerprint is listed (like `validpgpkeys=`)
+pub fn filter_keyring(
+    keyring: Vec<SigningKey>,
+    fingerprints: &[String],
+) -> Result<Vec<SigningKey>> {
+    let fingerprints = fingerprints
+        .iter()
+        .map(|fp| fp.replace(' ', "").to_uppercase())
+        .collect::<Vec<_>>();
+
+    let keyring = keyring
+        .into_iter()
+        .filter(|key| {
+            let fp = format!("{:X}", key.fingerprint());
+            let listed = fingerprints.contains(&fp);
+            debug!("Certificate {fp} is listed in allowed fingerprints: {listed}");
+            listed
+        })
+        .collect::<Vec<_>>();
+
+    for fp in &fingerprints {
+        if !keyring
+            .iter()
+            .any(|key| format!("{:X}", key.fingerprint()) == *fp)
+        {
+            warn!("Allowed fingerprint is not present in keyring: {fp}");
+        }
+    }
+
+    if keyring.is_empty() {
+        bail!("None of the allowed fingerprints are present in keyring: {fingerprints:?}");
+    }
+
+    Ok(keyring)
+}
+
 pub fn verify(keyring: &[SigningKey], sigs: &[Signature], msg: &[u8]) -> Result<Fingerprint> {
     for sig in sigs {
         let body = match sig.typ() {
@@ -75,3 +123,85 @@ pub fn verify(keyring: &[SigningKey], sigs: &[Signature], msg: &[u8]) -> Result<
 
     bail!("Failed to verify message")
 }
+
+/// List the valid user ids of the certificate a (sub)key belongs to
+pub fn user_ids(keyring: &[SigningKey], fingerprint: &Fingerprint) -> Result<Vec<String>> {
+    let cert = keyring
+        .iter()
+        .map(|key| &key.cert)
+        .find(|cert| {
+            cert.keys()
+                .any(|key| key.key().fingerprint() == *fingerprint)
+        })
+        .with_context(|| anyhow!("Failed to find certificate for key {fingerprint:X}"))?;
+
+    // only user ids with a valid, non-revoked binding signature count
+    let policy = StandardPolicy::new();
+    let user_ids = cert
+        .with_policy(&policy, None)
+        .context("Certificate is not valid under the standard policy")?
+        .userids()
+        .revoked(false)
+        .map(|uid| String::from_utf8_lossy(uid.userid().value()).into_owned())
+        .collect();
+    Ok(user_ids)
+}
+
+struct CleartextHelper<'a> {
+    keyring: &'a [SigningKey],
+    signer: Option<Fingerprint>,
+}
+
+impl VerificationHelper for CleartextHelper<'_> {
+    fn get_certs(&mut self, _ids: &[KeyHandle]) -> sequoia_openpgp::Result<Vec<Cert>> {
+        Ok(self.keyring.iter().map(|key| key.cert.clone()).collect())
+    }
+
+    fn check(&mut self, structure: MessageStructure) -> sequoia_openpgp::Result<()> {
+        for layer in structure.into_iter() {
+            let MessageLayer::SignatureGroup { results } = layer else {
+                continue;
+            };
+
+            for result in results {
+                match result {
+                    Ok(good) => {
+                        debug!("Successfully verified signature");
+                        self.signer = Some(good.ka.key().fingerprint());
+                        return Ok(());
+                    }
+                    Err(err) => {
+                        debug!("Signature verification failed: {err:#}");
+                    }
+                }
+            }
+        }
+
+        bail!("Failed to verify message")
+    }
+}
+
+/// Verify a message using the cleartext signature framework (like Debian's `InRelease`)
+///
+/// Returns the fingerprint of the signing key and the signed text.
+pub fn verify_cleartext(keyring: &[SigningKey], bytes: &[u8]) -> Result<(Fingerprint, Vec<u8>)> {
+    let policy = StandardPolicy::new();
+    let helper = CleartextHelper {
+        keyring,
+        signer: None,
+    };
+
+    let mut verifier = VerifierBuilder::from_bytes(bytes)?
+        .with_policy(&policy, None, helper)
+        .context("Failed to verify cleartext signed message")?;
+
+    let mut msg = Vec::new();
+    verifier.read_to_end(&mut msg)?;
+
+    let helper = verifier.into_helper();
+    let signer = helper
+        .signer
+        .context("Failed to find verified signature in cleartext signed message")?;
+
+    Ok((signer, msg))
+}
@@ -1,76 +1,333 @@
 use crate::chksums;
 use crate::errors::*;
+use crate::shell::{Env, MaskedExpansions, Var};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::iter;
 use std::ops::Not;
 use std::str;
-use yash_syntax::syntax::{self, TextUnit, Value, WordUnit};
+use yash_syntax::syntax::{self, Value};
 
-#[derive(Debug, Default, PartialEq)]
-pub struct Pkgbuild {
+/// Source protocols that are checked out from version control
+const VCS_PROTOCOLS: &[&str] = &["bzr", "fossil", "git", "hg", "svn"];
+
+/// An entry of the `source=` array
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    /// The filename the source is saved as
+    pub filename: String,
+    pub url: String,
+    pub vcs: Option<String>,
+    pub fragment: Option<String>,
+}
+
+impl Source {
+    /// Parse a source entry the way makepkg does, e.g. `name::git+https://example.com/repo.git#tag=v1.0`
+    pub fn parse(value: &str) -> Self {
+        let (name, url) = match value.split_once("::") {
+            Some((name, url)) => (Some(name), url),
+            None => (None, value),
+        };
+
+        let vcs = url
+            .split_once("://")
+            .map(|(scheme, _)| scheme.split('+').next().unwrap_or(scheme))
+            .filter(|proto| VCS_PROTOCOLS.contains(proto));
+
+        let Some(vcs) = vcs else {
+            let filename = name.unwrap_or_else(|| url.rsplit('/').next().unwrap_or(url));
+            return Source {
+                filename: filename.to_string(),
+                url: url.to_string(),
+                vcs: None,
+                fragment: None,
+            };
+        };
+
+        let url = url
+            .strip_prefix(vcs)
+            .and_then(|url| url.strip_prefix('+'))
+            .unwrap_or(url);
+        let (url, fragment) = match url.split_once('#') {
+            Some((url, fragment)) => (url, Some(fragment.to_string())),
+            None => (url, None),
+        };
+
+        let filename = if let Some(name) = name {
+            name.to_string()
+        } else {
+            let filename = url.split('?').next().unwrap_or(url).trim_end_matches('/');
+            let mut filename = filename.rsplit('/').next().unwrap_or(filename);
+            if vcs == "bzr" {
+                filename = filename.split_once("lp:").map_or(filename, |(_, f)| f);
+            }
+            if vcs == "git" {
+                filename = filename.split(".git").next().unwrap_or(filename);
+            }
+            if vcs == "fossil" {
+                format!("{filename}.fossil")
+            } else {
+                filename.to_string()
+            }
+        };
+
+        Source {
+            filename,
+            url: url.to_string(),
+            vcs: Some(vcs.to_string()),
+            fragment,
+        }
+    }
+}
+
+/// The `source=` entry an artifact was matched with
+#[derive(Debug, PartialEq)]
+pub struct SourceMatch {
+    pub index: usize,
+    pub source: Option<Source>,
+    /// The checksum arrays that had an entry matching the artifact
+    pub algorithms: Vec<Algorithm>,
+}
+
+/// The checksum algorithms supported by makepkg
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Algorithm {
+    Md5,
+    Sha1,
+    Sha224,
+    Sha256,
+    Sha384,
+    Sha512,
+    Blake2b,
+    Cksum,
+}
+
+impl Algorithm {
+    pub const ALL: [Algorithm; 8] = [
+        Algorithm::Md5,
+        Algorithm::Sha1,
+        Algorithm::Sha224,
+        Algorithm::Sha256,
+        Algorithm::Sha384,
+        Algorithm::Sha512,
+        Algorithm::Blake2b,
+        Algorithm::Cksum,
+    ];
+
+    /// The name of the array in the PKGBUILD, e.g. `sha256sums`
+    pub fn array_name(&self) -> &'static str {
+        match self {
+            Algorithm::Md5 => "md5sums",
+            Algorithm::Sha1 => "sha1sums",
+            Algorithm::Sha224 => "sha224sums",
+            Algorithm::Sha256 => "sha256sums",
+            Algorithm::Sha384 => "sha384sums",
+            Algorithm::Sha512 => "sha512sums",
+            Algorithm::Blake2b => "b2sums",
+            Algorithm::Cksum => "cksums",
+        }
+    }
+
+    /// Weak algorithms are never sufficient to authenticate an artifact on their own
+    pub fn is_weak(&self) -> bool {
+        matches!(self, Algorithm::Md5 | Algorithm::Sha1 | Algorithm::Cksum)
+    }
+
+    pub fn digest(&self, bytes: &[u8]) -> String {
+        match self {
+            Algorithm::Md5 => chksums::md5(bytes),
+            Algorithm::Sha1 => chksums::sha1(bytes),
+            Algorithm::Sha224 => chksums::sha224(bytes),
+            Algorithm::Sha256 => chksums::sha256(bytes),
+            Algorithm::Sha384 => chksums::sha384(bytes),
+            Algorithm::Sha512 => chksums::sha512(bytes),
+            Algorithm::Blake2b => chksums::blake2b(bytes),
+            Algorithm::Cksum => chksums::cksum(bytes),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.array_name())
+    }
+}
+
+/// The `source=` array along with its checksum arrays
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Sources {
+    pub source: Vec<Source>,
+    pub md5sums: Vec<String>,
+    pub sha1sums: Vec<String>,
+    pub sha224sums: Vec<String>,
     pub sha256sums: Vec<String>,
+    pub sha384sums: Vec<String>,
     pub sha512sums: Vec<String>,
     pub b2sums: Vec<String>,
+    pub cksums: Vec<String>,
 }
 
-impl Pkgbuild {
-    pub fn has_artifact_by_checksum(&self, content: &[u8]) -> Result<()> {
-        let sha256 = self
-            .sha256sums
-            .is_empty()
-            .not()
-            .then(|| chksums::sha256(content));
-        let sha512 = self
-            .sha512sums
-            .is_empty()
-            .not()
-            .then(|| chksums::sha512(content));
-        let blake2b = self
-            .b2sums
-            .is_empty()
-            .not()
-            .then(|| chksums::blake2b(content));
-
-        if self.has_match_for_checksums(sha256.as_deref(), sha512.as_deref(), blake2b.as_deref()) {
-            Ok(())
+impl Sources {
+    /// Read the arrays from the environment, `suffix` is used for architecture specific arrays (e.g. `_x86_64`)
+    fn from_env(env: &Env, suffix: &str) -> Result<Self> {
+        let array = |name: &str| -> Result<Vec<String>> {
+            let name = format!("{name}{suffix}");
+            if !env.contains(&name) {
+                return Ok(Vec::new());
+            }
+            Ok(env.get(&name)?.clone().into_array())
+        };
+
+        let source = match array("source") {
+            Ok(source) => source.iter().map(|value| Source::parse(value)).collect(),
+            Err(err) => {
+                // without all entries the offsets of the checksum arrays can't be mapped reliably
+                warn!("Failed to resolve source{suffix}= array, ignoring: {err:#}");
+                Vec::new()
+            }
+        };
+
+        let mut sources = Sources {
+            source,
+            ..Default::default()
+        };
+        for algorithm in Algorithm::ALL {
+            *sources.checksums_mut(algorithm) = array(algorithm.array_name())?;
+        }
+        Ok(sources)
+    }
+
+    pub fn checksums(&self, algorithm: Algorithm) -> &[String] {
+        match algorithm {
+            Algorithm::Md5 => &self.md5sums,
+            Algorithm::Sha1 => &self.sha1sums,
+            Algorithm::Sha224 => &self.sha224sums,
+            Algorithm::Sha256 => &self.sha256sums,
+            Algorithm::Sha384 => &self.sha384sums,
+            Algorithm::Sha512 => &self.sha512sums,
+            Algorithm::Blake2b => &self.b2sums,
+            Algorithm::Cksum => &self.cksums,
+        }
+    }
+
+    pub fn checksums_mut(&mut self, algorithm: Algorithm) -> &mut Vec<String> {
+        match algorithm {
+            Algorithm::Md5 => &mut self.md5sums,
+            Algorithm::Sha1 => &mut self.sha1sums,
+            Algorithm::Sha224 => &mut self.sha224sums,
+            Algorithm::Sha256 => &mut self.sha256sums,
+            Algorithm::Sha384 => &mut self.sha384sums,
+            Algorithm::Sha512 => &mut self.sha512sums,
+            Algorithm::Blake2b => &mut self.b2sums,
+            Algorithm::Cksum => &mut self.cksums,
+        }
+    }
+
+    /// Append the arrays of an architecture, the same way makepkg does
+    fn extend(&mut self, other: &Sources) {
+        // if the generic source= array couldn't be resolved, the offsets would be off
+        if Algorithm::ALL
+            .iter()
+            .all(|algorithm| self.checksums(*algorithm).len() <= self.source.len())
+        {
+            self.source.extend(other.source.iter().cloned());
+        }
+        for algorithm in Algorithm::ALL {
+            self.checksums_mut(algorithm)
+                .extend(other.checksums(algorithm).iter().cloned());
+        }
+    }
+
+    pub fn has_artifact_by_checksum(&self, content: &[u8]) -> Result<SourceMatch> {
+        let digests = Algorithm::ALL
+            .into_iter()
+            .filter(|algorithm| self.checksums(*algorithm).is_empty().not())
+            .map(|algorithm| (algorithm, algorithm.digest(content)))
+            .collect::<BTreeMap<_, _>>();
+
+        let (index, algorithms) = self.has_match_for_checksums(&digests)?;
+
+        let weak = algorithms
+            .iter()
+            .filter(|algorithm| algorithm.is_weak())
+            .map(|algorithm| algorithm.array_name())
+            .collect::<Vec<_>>();
+        if !weak.is_empty() {
+            warn!("Artifact also matches weak checksums, these are not relied upon: {weak:?}");
+        }
+
+        let source = self.source.get(index).cloned();
+        if let Some(source) = &source {
+            info!(
+                "Artifact matches source #{index}: filename={:?}, url={:?}",
+                source.filename, source.url
+            );
         } else {
-            bail!("PKGBUILD does not seem to have any matching sources, sha256={sha256:?}, sha512={sha512:?}, blake2b={blake2b:?}")
+            warn!(
+                "Artifact matches source #{index}, but the source= entry could not be determined"
+            );
         }
+        Ok(SourceMatch {
+            index,
+            source,
+            algorithms,
+        })
     }
 
+    /// Find the source= offset matching all given digests
+    ///
+    /// Returns the offset and the algorithms that matched, at least one of them is not weak.
     pub fn has_match_for_checksums(
         &self,
-        sha256: Option<&str>,
-        sha512: Option<&str>,
-        blake2b: Option<&str>,
-    ) -> bool {
-        let Some(max) = [
-            self.sha256sums.len(),
-            self.sha512sums.len(),
-            self.b2sums.len(),
-        ]
-        .into_iter()
-        .max() else {
-            return false;
-        };
+        digests: &BTreeMap<Algorithm, String>,
+    ) -> Result<(usize, Vec<Algorithm>)> {
+        let max = Algorithm::ALL
+            .iter()
+            .map(|algorithm| self.checksums(*algorithm).len())
+            .max()
+            .unwrap_or(0);
 
+        let mut weak_only = Vec::new();
         for idx in 0..max {
-            let cmps = [
-                Self::compare_chksum(&self.sha256sums, idx, sha256),
-                Self::compare_chksum(&self.sha512sums, idx, sha512),
-                Self::compare_chksum(&self.b2sums, idx, blake2b),
-            ];
+            let cmps = Algorithm::ALL.map(|algorithm| {
+                let expected = digests.get(&algorithm).map(String::as_str);
+                let list = self.checksums(algorithm);
+                (algorithm, Self::compare_chksum(list, idx, expected))
+            });
 
-            if cmps.iter().any(|c| *c == Compare::Mismatch) {
+            if cmps.iter().any(|(_, c)| *c == Compare::Mismatch) {
                 continue;
             }
 
-            if cmps.iter().any(|c| *c == Compare::StrongMatch) {
+            let matched = cmps
+                .iter()
+                .filter(|(_, c)| *c == Compare::StrongMatch)
+                .map(|(algorithm, _)| *algorithm)
+                .collect::<Vec<_>>();
+
+            if matched.iter().any(|algorithm| !algorithm.is_weak()) {
                 info!("PKGBUILD has source= offset at #{idx:?} matching all checksums of artifact");
-                return true;
+                return Ok((idx, matched));
+            }
+
+            if !matched.is_empty() {
+                warn!("PKGBUILD has source= offset at #{idx:?} matching only weak checksums: {matched:?}");
+                weak_only.push((idx, matched));
             }
         }
 
         debug!("Could not find any matches in combined checksum arrays");
-        false
+        if let Some((idx, matched)) = weak_only.first() {
+            let names = matched
+                .iter()
+                .map(|algorithm| algorithm.array_name())
+                .collect::<Vec<_>>();
+            bail!(
+                "PKGBUILD source #{idx} only matches weak checksums ({}), refusing to rely on them",
+                names.join(", ")
+            );
+        }
+        bail!("PKGBUILD does not seem to have any matching sources, digests={digests:?}")
     }
 
     pub fn compare_chksum(list: &[String], idx: usize, expected: Option<&str>) -> Compare {
@@ -85,6 +342,143 @@ impl Pkgbuild {
     }
 }
 
+#[derive(Debug, Default, PartialEq)]
+pub struct Pkgbuild {
+    pub pkgbase: Option<String>,
+    pub pkgname: Vec<String>,
+    pub arch: Vec<String>,
+    /// Fingerprints of the keys allowed to sign upstream sources
+    pub validpgpkeys: Vec<String>,
+    pub sources: Sources,
+    /// Architecture specific arrays, like `source_x86_64=` or `b2sums_aarch64=`
+    pub arch_sources: BTreeMap<String, Sources>,
+}
+
+impl Pkgbuild {
+    /// Build the PKGBUILD model from the variables of an evaluated script
+    fn from_env(env: &Env) -> Result<Self> {
+        let array = |name: &str| -> Result<Vec<String>> {
+            if !env.contains(name) {
+                return Ok(Vec::new());
+            }
+            Ok(env.get(name)?.clone().into_array())
+        };
+        let pkgbase = if env.contains("pkgbase") {
+            Some(env.get("pkgbase")?.as_scalar())
+        } else {
+            None
+        };
+
+        let suffixes = env
+            .names()
+            .filter_map(|name| {
+                source_arrays().find_map(|array| {
+                    name.strip_prefix(array)
+                        .and_then(|suffix| suffix.strip_prefix('_'))
+                })
+            })
+            .collect::<BTreeSet<_>>();
+
+        let mut arch_sources = BTreeMap::new();
+        for suffix in suffixes {
+            debug!("Found architecture specific arrays for {suffix:?}");
+            let sources = Sources::from_env(env, &format!("_{suffix}"))?;
+            arch_sources.insert(suffix.to_string(), sources);
+        }
+
+        Ok(Pkgbuild {
+            pkgbase,
+            pkgname: array("pkgname")?,
+            arch: array("arch")?,
+            validpgpkeys: array("validpgpkeys")?,
+            sources: Sources::from_env(env, "")?,
+            arch_sources,
+        })
+    }
+
+    /// The name of the package base, makepkg defaults to the first pkgname
+    pub fn base(&self) -> Option<&str> {
+        self.pkgbase
+            .as_deref()
+            .or_else(|| self.pkgname.first().map(String::as_str))
+    }
+
+    /// Confirm a package (e.g. as described by .BUILDINFO) is produced by this PKGBUILD
+    pub fn check_package(&self, pkgbase: &str, pkgname: &str) -> Result<()> {
+        let Some(base) = self.base() else {
+            bail!("PKGBUILD does not declare any pkgname");
+        };
+        if base != pkgbase {
+            bail!("Package has pkgbase={pkgbase:?}, but PKGBUILD has pkgbase={base:?}");
+        }
+        if !self.pkgname.iter().any(|name| name == pkgname) {
+            bail!(
+                "Package {pkgname:?} is not produced by PKGBUILD, pkgname={:?}",
+                self.pkgname
+            );
+        }
+        debug!("Package {pkgname:?} is produced by PKGBUILD (pkgbase={base:?})");
+        Ok(())
+    }
+
+    /// Every package needs a package function, makepkg refuses to build the PKGBUILD otherwise
+    fn check_package_functions(&self, functions: &[String]) -> Result<()> {
+        for name in &self.pkgname {
+            let func = format!("package_{name}");
+            let found = functions.contains(&func)
+                || (self.pkgname.len() == 1 && functions.iter().any(|f| f == "package"));
+            if !found {
+                bail!("PKGBUILD has no package function for {name:?}");
+            }
+        }
+        Ok(())
+    }
+
+    /// The generic arrays combined with the ones of the given architecture
+    pub fn sources_for_arch(&self, arch: &str) -> Sources {
+        let mut sources = self.sources.clone();
+        if let Some(arch_sources) = self.arch_sources.get(arch) {
+            sources.extend(arch_sources);
+        }
+        sources
+    }
+
+    /// Find the source entry matching the artifact
+    ///
+    /// If the architecture of the package is known (`pkgarch` in .BUILDINFO) only the arrays
+    /// relevant for it are considered, otherwise each architecture is tried.
+    pub fn has_artifact_by_checksum(
+        &self,
+        content: &[u8],
+        arch: Option<&str>,
+    ) -> Result<SourceMatch> {
+        if let Some(arch) = arch {
+            debug!("Using source arrays for architecture {arch:?}");
+            return self
+                .sources_for_arch(arch)
+                .has_artifact_by_checksum(content);
+        }
+
+        match self.sources.has_artifact_by_checksum(content) {
+            Ok(m) => return Ok(m),
+            Err(err) if self.arch_sources.is_empty() => return Err(err),
+            Err(err) => debug!("No match in generic source arrays: {err:#}"),
+        }
+
+        for arch in self.arch_sources.keys() {
+            debug!("Trying source arrays for architecture {arch:?}");
+            if let Ok(m) = self
+                .sources_for_arch(arch)
+                .has_artifact_by_checksum(content)
+            {
+                return Ok(m);
+            }
+        }
+
+        bail!("PKGBUILD does not seem to have any matching sources for any architecture")
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Compare {
     StrongMatch,
@@ -92,60 +486,257 @@ pub enum Compare {
     Mismatch,
 }
 
-pub fn parse(bytes: &[u8]) -> Result<Pkgbuild> {
-    let script = str::from_utf8(bytes)?;
-    let parsed: syntax::List = script
-        .parse()
-        .map_err(|err| anyhow!("Failed to parse input as shell script: {:#?}", err))?;
+/// Evaluate an assignment without executing anything
+fn evaluate(env: &Env, expansions: &MaskedExpansions, value: &Value) -> Result<Var> {
+    match value {
+        Value::Scalar(word) => env
+            .expand(&expansions.unmask(&word.to_string()))
+            .map(Var::Scalar),
+        Value::Array(words) => {
+            let mut values = Vec::new();
+            for word in words {
+                values.extend(env.expand_words(&expansions.unmask(&word.to_string()))?);
+            }
+            Ok(Var::Array(values))
+        }
+    }
+}
 
-    let mut pkgbuild = Pkgbuild::default();
+/// Whether a variable is a `source=` or checksum array, including architecture specific ones
+fn is_source_array(name: &str) -> bool {
+    source_arrays().any(|array| {
+        name.strip_prefix(array)
+            .is_some_and(|suffix| suffix.is_empty() || suffix.starts_with('_'))
+    })
+}
 
-    for item in &parsed.0 {
-        for cmd in &item.and_or.first.commands {
-            let syntax::Command::Simple(cmd) = cmd.as_ref() else {
-                continue;
+/// The arrays that can have an architecture specific variant, like `sha256sums_x86_64`
+fn source_arrays() -> impl Iterator<Item = &'static str> {
+    iter::once("source").chain(
+        Algorithm::ALL
+            .iter()
+            .map(|algorithm| algorithm.array_name()),
+    )
+}
+
+/// How a command is executed when makepkg sources the PKGBUILD
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Context {
+    /// Always executed
+    Unconditional,
+    /// Only executed depending on a condition, like `if`, `case` or `&&`
+    Conditional,
+    /// Executed in a function, subshell or pipeline, assignments don't persist
+    Isolated,
+}
+
+/// Builtins that take assignments as arguments, like `declare -a source=(...)`
+const DECLARATION_BUILTINS: &[&str] = &["declare", "typeset", "local", "readonly", "export"];
+
+/// Builtins that assign to a variable given by name, like `read -a source`
+const READING_BUILTINS: &[&str] = &["read", "mapfile", "readarray", "printf"];
+
+/// Walk the syntax tree and record all assignments that persist
+#[derive(Default)]
+struct Walker {
+    env: Env,
+    expansions: MaskedExpansions,
+    functions: Vec<String>,
+    /// The function whose body is currently walked
+    current_function: Option<String>,
+    /// Functions that assign source or checksum arrays, calling them would change the arrays
+    assigning_functions: BTreeSet<String>,
+}
+
+impl Walker {
+    fn list(&mut self, list: &syntax::List, ctx: Context) -> Result<()> {
+        for item in &list.0 {
+            self.and_or(&item.and_or, ctx)?;
+        }
+        Ok(())
+    }
+
+    fn and_or(&mut self, and_or: &syntax::AndOrList, ctx: Context) -> Result<()> {
+        self.pipeline(&and_or.first, ctx)?;
+        for (_, pipeline) in &and_or.rest {
+            self.pipeline(pipeline, ctx.max(Context::Conditional))?;
+        }
+        Ok(())
+    }
+
+    fn pipeline(&mut self, pipeline: &syntax::Pipeline, ctx: Context) -> Result<()> {
+        let ctx = if pipeline.commands.len() > 1 {
+            ctx.max(Context::Isolated)
+        } else {
+            ctx
+        };
+        for cmd in &pipeline.commands {
+            self.command(cmd, ctx)?;
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, cmd: &syntax::Command, ctx: Context) -> Result<()> {
+        match cmd {
+            syntax::Command::Simple(cmd) => self.simple(cmd, ctx),
+            syntax::Command::Compound(cmd) => self.compound(&cmd.command, ctx),
+            syntax::Command::Function(func) => {
+                let name = func.name.to_string();
+                debug!("Found function definition: {name}");
+                self.functions.push(name.clone());
+
+                let outer = self.current_function.replace(name);
+                let ret = self.compound(&func.body.command, ctx.max(Context::Isolated));
+                self.current_function = outer;
+                ret
+            }
+        }
+    }
+
+    fn compound(&mut self, cmd: &syntax::CompoundCommand, ctx: Context) -> Result<()> {
+        let cond = ctx.max(Context::Conditional);
+        match cmd {
+            syntax::CompoundCommand::Grouping(list) => self.list(list, ctx)?,
+            syntax::CompoundCommand::Subshell { body, .. } => {
+                self.list(body, ctx.max(Context::Isolated))?
+            }
+            syntax::CompoundCommand::For { body, .. } => self.list(body, cond)?,
+            syntax::CompoundCommand::While { condition, body }
+            | syntax::CompoundCommand::Until { condition, body } => {
+                self.list(condition, ctx)?;
+                self.list(body, cond)?;
+            }
+            syntax::CompoundCommand::If {
+                condition,
+                body,
+                elifs,
+                r#else,
+            } => {
+                self.list(condition, ctx)?;
+                self.list(body, cond)?;
+                for elif in elifs {
+                    self.list(&elif.condition, cond)?;
+                    self.list(&elif.body, cond)?;
+                }
+                if let Some(r#else) = r#else {
+                    self.list(r#else, cond)?;
+                }
+            }
+            syntax::CompoundCommand::Case { items, .. } => {
+                for item in items {
+                    self.list(&item.body, cond)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Refuse commands that modify source or checksum arrays in ways that aren't evaluated
+    fn check_command(&mut self, words: &[String], ctx: Context) -> Result<()> {
+        let Some((name, args)) = words.split_first() else {
+            return Ok(());
+        };
+
+        let modified = if self.assigning_functions.contains(name) {
+            Some(format!("function {name:?}"))
+        } else if name == "eval" {
+            let code = args.join(" ");
+            source_arrays()
+                .find(|array| code.contains(array))
+                .map(|array| format!("{array:?} with eval"))
+        } else if DECLARATION_BUILTINS.contains(&name.as_str()) {
+            args.iter()
+                .filter_map(|arg| arg.split_once('='))
+                .map(|(var, _)| var.trim_end_matches('+'))
+                .find(|var| is_source_array(var))
+                .map(|var| format!("{var:?} with {name:?}"))
+        } else if name == "source" || name == "." {
+            // the other file isn't inspected, it may assign anything
+            Some(format!("another file loaded with {name:?}"))
+        } else if READING_BUILTINS.contains(&name.as_str()) {
+            args.iter()
+                .find(|arg| is_source_array(arg))
+                .map(|var| format!("{var:?} with {name:?}"))
+        } else {
+            None
+        };
+        let Some(modified) = modified else {
+            return Ok(());
+        };
+
+        if ctx != Context::Isolated {
+            bail!("PKGBUILD modifies source or checksum arrays through {modified}, this is not supported");
+        }
+        // calling the surrounding function would modify them too
+        if let Some(func) = &self.current_function {
+            self.assigning_functions.insert(func.clone());
+        }
+        warn!("PKGBUILD modifies source or checksum arrays through {modified} in a function or subshell");
+        Ok(())
+    }
+
+    fn simple(&mut self, cmd: &syntax::SimpleCommand, ctx: Context) -> Result<()> {
+        // assignments in front of a command only apply to that command
+        if !cmd.words.is_empty() {
+            let words = cmd
+                .words
+                .iter()
+                .map(|(word, _)| self.expansions.unmask(&word.to_string()))
+                .collect::<Vec<_>>();
+            return self.check_command(&words, ctx);
+        }
+
+        for assign in &cmd.assigns {
+            let name = assign.name.as_str();
+
+            // handle bash-style `+=` assignments
+            let (name, append) = match name.strip_suffix('+') {
+                Some(name) => (name, true),
+                None => (name, false),
             };
-            for assign in &cmd.assigns {
-                let name = assign.name.as_str();
-
-                // handle bash-style `+=` assignments
-                let name = name.strip_suffix('+').unwrap_or(name);
-                debug!("Found assignment to {name:?}");
-
-                let target = match name {
-                    "sha256sums" => &mut pkgbuild.sha256sums,
-                    "sha512sums" => &mut pkgbuild.sha512sums,
-                    "b2sums" => &mut pkgbuild.b2sums,
-                    _ => continue,
-                };
-
-                let Value::Array(values) = &assign.value else {
-                    continue;
-                };
-
-                for value in values {
-                    for unit in &value.units {
-                        trace!("Found word unit: {unit:?}");
-
-                        match unit {
-                            WordUnit::SingleQuote(text) => target.push(text.to_string()),
-                            WordUnit::DoubleQuote(text) => {
-                                let mut s = String::new();
-                                for unit in &text.0 {
-                                    if let TextUnit::Literal(chr) = unit {
-                                        s.push(*chr);
-                                    }
-                                }
-                                target.push(s);
-                            }
-                            other => bail!("Unsupported word unit: {other:?}"),
+            debug!("Found assignment to {name:?} ({ctx:?})");
+
+            if is_source_array(name) {
+                match ctx {
+                    Context::Unconditional => (),
+                    Context::Conditional => {
+                        bail!("PKGBUILD assigns {name:?} conditionally, the value depends on how it's evaluated")
+                    }
+                    Context::Isolated => {
+                        if let Some(func) = &self.current_function {
+                            self.assigning_functions.insert(func.clone());
                         }
+                        warn!("PKGBUILD assigns {name:?} in a function or subshell, ignoring")
                     }
                 }
             }
+
+            if ctx == Context::Isolated {
+                continue;
+            }
+
+            let value = evaluate(&self.env, &self.expansions, &assign.value);
+            self.env.assign(name, append, value);
         }
+        Ok(())
     }
+}
+
+pub fn parse(bytes: &[u8]) -> Result<Pkgbuild> {
+    let script = str::from_utf8(bytes)?;
+    let (script, expansions) = MaskedExpansions::mask(script);
+    let parsed: syntax::List = script
+        .parse()
+        .map_err(|err| anyhow!("Failed to parse input as shell script: {:#?}", err))?;
+
+    let mut walker = Walker {
+        expansions,
+        ..Default::default()
+    };
+    walker.list(&parsed, Context::Unconditional)?;
 
+    let pkgbuild = Pkgbuild::from_env(&walker.env)?;
+    pkgbuild.check_package_functions(&walker.functions)?;
     Ok(pkgbuild)
 }
 
@@ -170,6 +761,7 @@ b2sums=('cd594be73fcf632544195d09518901b1055ae86dcf463a5d446a83beba66073c70a9dfb
         assert_eq!(
             pkgbuild,
             Pkgbuild {
+                sources: Sources {
                 sha256sums: vec![
                     "7a1258a5dfc48c54cea1092adddb6bcfb1fcf19c7272c0a6a9e1d2d7daee6e12".to_string(),
                     "f9a4925f7d7bb7de54e17cd9ad7c584dfae88ad182d943b79cf403425000f128".to_string(),
@@ -179,6 +771,404 @@ b2sums=('cd594be73fcf632544195d09518901b1055ae86dcf463a5d446a83beba66073c70a9dfb
                     "cd594be73fcf632544195d09518901b1055ae86dcf463a5d446a83beba66073c70a9dfb75efd9d826c2ecf7215ab6cd76128a20104d5ef4ea57470061d2e29bf".to_string(),
                     "f4f89b720bcbe23c5413c6cbc2d0793d8e379fc53861a6fbd83f506e56a86132bb92236498b4357310b09e51fd05aa5ccc941649a4f205fb4e53cb6bc32cdd64".to_string(),
                 ],
+                ..Default::default()
+                },
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_source() {
+        init();
+
+        let script = b"source=('cmatrix-2.0.tar.gz::https://github.com/abishekvashok/cmatrix/archive/v2.0.tar.gz'
+        \"https://example.com/foo-1.0.tar.xz\"
+        'fix-build.patch')
+sha256sums=('ad93ba39acd383696ab6a9ebbed1259ecf2d3cf9f49d6b97038c66f80749e99a'
+            'SKIP'
+            '5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03')
+";
+        let pkgbuild = parse(script).unwrap();
+        assert_eq!(
+            pkgbuild.sources.source,
+            vec![
+                Source {
+                    filename: "cmatrix-2.0.tar.gz".to_string(),
+                    url: "https://github.com/abishekvashok/cmatrix/archive/v2.0.tar.gz".to_string(),
+                    vcs: None,
+                    fragment: None,
+                },
+                Source {
+                    filename: "foo-1.0.tar.xz".to_string(),
+                    url: "https://example.com/foo-1.0.tar.xz".to_string(),
+                    vcs: None,
+                    fragment: None,
+                },
+                Source {
+                    filename: "fix-build.patch".to_string(),
+                    url: "fix-build.patch".to_string(),
+                    vcs: None,
+                    fragment: None,
+                },
+            ]
+        );
+
+        let m = pkgbuild.has_artifact_by_checksum(b"hello\n", None).unwrap();
+        assert_eq!(m.index, 2);
+        assert_eq!(m.source.unwrap().filename, "fix-build.patch");
+    }
+
+    #[test]
+    fn test_parse_array_expansion() {
+        init();
+
+        let script = br#"pkgname=foo
+pkgver=1.0
+_common=(foo-1.0.tar.gz "fix build.patch")
+source=("${_common[@]}" extra.patch)
+sha256sums=(SKIP SKIP SKIP)
+package() {
+    true
+}
+"#;
+        let pkgbuild = parse(script).unwrap();
+        assert_eq!(
+            pkgbuild.sources.source,
+            vec![
+                Source::parse("foo-1.0.tar.gz"),
+                Source::parse("fix build.patch"),
+                Source::parse("extra.patch"),
+            ]
+        );
+
+        let script = br#"pkgname=foo
+pkgver=1.0
+_common=(foo-1.0.tar.gz fix.patch)
+source=("prefix-${_common[@]}" extra.patch)
+package() {
+    true
+}
+"#;
+        // the number of elements is unknown, so the offsets of the checksums can't be mapped
+        let pkgbuild = parse(script).unwrap();
+        assert!(pkgbuild.sources.source.is_empty());
+    }
+
+    #[test]
+    fn test_parse_variables() {
+        init();
+
+        let script = br#"pkgname=cmatrix
+pkgver=2.0
+_commit=5c082c64a1296859a11bee60b8a2a51a8b2e5a3b
+source=("$pkgname-$pkgver.tar.gz::https://github.com/abishekvashok/$pkgname/archive/v${pkgver}.tar.gz"
+        "https://example.com/${pkgname}_${pkgver//./_}.patch"
+        "git+https://example.com/repo.git#commit=${_commit}")
+sha256sums=(ad93ba39acd383696ab6a9ebbed1259ecf2d3cf9f49d6b97038c66f80749e99a
+            SKIP
+            SKIP)
+package() {
+    true
+}
+"#;
+        let pkgbuild = parse(script).unwrap();
+        assert_eq!(
+            pkgbuild.sources.source,
+            vec![
+                Source::parse("cmatrix-2.0.tar.gz::https://github.com/abishekvashok/cmatrix/archive/v2.0.tar.gz"),
+                Source::parse("https://example.com/cmatrix_2_0.patch"),
+                Source::parse("git+https://example.com/repo.git#commit=5c082c64a1296859a11bee60b8a2a51a8b2e5a3b"),
+            ]
+        );
+        assert_eq!(
+            pkgbuild.sources.sha256sums,
+            &[
+                "ad93ba39acd383696ab6a9ebbed1259ecf2d3cf9f49d6b97038c66f80749e99a",
+                "SKIP",
+                "SKIP"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_command_substitution() {
+        init();
+
+        let script = br#"pkgver=$(curl https://example.com)
+source=("https://example.com/foo-$pkgver.tar.gz")
+sha256sums=('ad93ba39acd383696ab6a9ebbed1259ecf2d3cf9f49d6b97038c66f80749e99a')
+"#;
+        let pkgbuild = parse(script).unwrap();
+        assert_eq!(pkgbuild.sources.source, vec![]);
+        assert_eq!(pkgbuild.sources.sha256sums.len(), 1);
+
+        let script = br#"sha256sums=("$(curl https://example.com)")
+"#;
+        assert!(parse(script).is_err());
+    }
+
+    #[test]
+    fn test_parse_arch_specific() {
+        init();
+
+        let script = br#"arch=('x86_64' 'aarch64')
+source=("https://example.com/foo-1.0.tar.gz")
+source_x86_64=("https://example.com/foo-1.0-x86_64.bin")
+source_aarch64=("https://example.com/foo-1.0-aarch64.bin")
+sha256sums=('ad93ba39acd383696ab6a9ebbed1259ecf2d3cf9f49d6b97038c66f80749e99a')
+sha256sums_x86_64=('SKIP')
+sha256sums_aarch64=('5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03')
+"#;
+        let pkgbuild = parse(script).unwrap();
+        assert_eq!(pkgbuild.arch, &["x86_64", "aarch64"]);
+        assert_eq!(
+            pkgbuild.arch_sources.keys().collect::<Vec<_>>(),
+            &["aarch64", "x86_64"]
+        );
+
+        let sources = pkgbuild.sources_for_arch("aarch64");
+        assert_eq!(sources.source.len(), 2);
+        assert_eq!(sources.sha256sums.len(), 2);
+
+        let m = pkgbuild
+            .has_artifact_by_checksum(b"hello\n", Some("aarch64"))
+            .unwrap();
+        assert_eq!(m.index, 1);
+        assert_eq!(m.source.unwrap().filename, "foo-1.0-aarch64.bin");
+
+        let m = pkgbuild.has_artifact_by_checksum(b"hello\n", None).unwrap();
+        assert_eq!(m.index, 1);
+
+        assert!(pkgbuild
+            .has_artifact_by_checksum(b"hello\n", Some("x86_64"))
+            .is_err());
+        assert!(pkgbuild
+            .has_artifact_by_checksum(b"hello\n", Some("any"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_weak_checksums() {
+        init();
+
+        let script = b"source=('foo.patch' 'bar.patch')
+md5sums=('b1946ac92492d2347c6235b4d2611184' 'b1946ac92492d2347c6235b4d2611184')
+cksums=('SKIP' '3015617425')
+sha384sums=('SKIP'
+            '1d0f284efe3edea4b9ca3bd514fa134b17eae361ccc7a1eefeff801b9bd6604e01f21f6bf249ef030599f0c218f2ba8c')
+";
+        let pkgbuild = parse(script).unwrap();
+        let sources = &pkgbuild.sources;
+        assert_eq!(sources.md5sums.len(), 2);
+        assert_eq!(sources.cksums.len(), 2);
+
+        let m = pkgbuild.has_artifact_by_checksum(b"hello\n", None).unwrap();
+        assert_eq!(m.index, 1);
+        assert_eq!(
+            m.algorithms,
+            &[Algorithm::Md5, Algorithm::Sha384, Algorithm::Cksum]
+        );
+
+        let script = b"source=('foo.patch')
+md5sums=('b1946ac92492d2347c6235b4d2611184')
+sha1sums=('f572d396fae9206628714fb2ce00f72e94f2258f')
+";
+        let pkgbuild = parse(script).unwrap();
+        let err = pkgbuild
+            .has_artifact_by_checksum(b"hello\n", None)
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("only matches weak checksums (md5sums, sha1sums)"));
+    }
+
+    #[test]
+    fn test_parse_control_flow() {
+        init();
+
+        let script = br#"source=("https://example.com/foo-1.0.tar.gz")
+sha256sums=('5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03')
+if [ -n "$foo" ]; then
+    pkgver=1.0
+fi
+package() {
+    sha512sums=('SKIP')
+}
+(sha384sums=('SKIP'))
+"#;
+        let pkgbuild = parse(script).unwrap();
+        let sources = &pkgbuild.sources;
+        assert_eq!(
+            sources.sha256sums,
+            &["5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03"]
+        );
+        assert!(sources.sha512sums.is_empty());
+        assert!(sources.sha384sums.is_empty());
+    }
+
+    #[test]
+    fn test_parse_conditional_sources() {
+        init();
+
+        for script in [
+            &b"sha256sums=('SKIP')
+if false; then
+    sha256sums=('5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03')
+fi
+"[..],
+            b"case \"$CARCH\" in
+    x86_64) sha256sums=('SKIP') ;;
+esac
+",
+            b"true && b2sums=('SKIP')\n",
+        ] {
+            assert!(parse(script).is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_indirect_assignments() {
+        init();
+
+        for script in [
+            &b"declare -r sha256sums='SKIP'\n"[..],
+            b"readonly b2sums=SKIP\n",
+            b"eval 'sha256sums=(SKIP)'\n",
+            b"read -a source < sources.txt\n",
+            b"_sums() {\n    sha256sums=('SKIP')\n}\n_sums\n",
+            b"_sums() {\n    local sha256sums=SKIP\n}\n_wrapper() {\n    _sums\n}\n_wrapper\n",
+        ] {
+            assert!(
+                parse(script).is_err(),
+                "{}",
+                String::from_utf8_lossy(script)
+            );
+        }
+
+        // functions are fine as long as they are not called while sourcing
+        parse(b"build() {\n    local sha256sums=SKIP\n}\ndeclare -r _x=1\n").unwrap();
+    }
+
+    #[test]
+    fn test_parse_sourced_files() {
+        init();
+
+        for script in [
+            &b"source ./sums.sh\n"[..],
+            b". ./sums.sh\n",
+            b"if [ -e sums.sh ]; then\n    . ./sums.sh\nfi\n",
+            b"_load() {\n    source ./sums.sh\n}\n_load\n",
+            b"_load() {\n    . ./sums.sh\n}\n_load\n",
+        ] {
+            assert!(
+                parse(script).is_err(),
+                "{}",
+                String::from_utf8_lossy(script)
+            );
+        }
+
+        // loading files is only a problem if it happens while sourcing
+        parse(b"build() {\n    source ./env.sh\n}\npackage() {\n    . ./env.sh\n}\n").unwrap();
+    }
+
+    #[test]
+    fn test_is_source_array() {
+        assert!(is_source_array("source"));
+        assert!(is_source_array("source_x86_64"));
+        assert!(is_source_array("b2sums_aarch64"));
+        assert!(!is_source_array("sources"));
+        assert!(!is_source_array("pkgver"));
+    }
+
+    #[test]
+    fn test_parse_validpgpkeys() {
+        init();
+
+        let script = b"source=('https://example.com/foo-1.0.tar.gz'
+        'https://example.com/foo-1.0.tar.gz.sig')
+validpgpkeys=('64B13F7117D6E07D661BBCE0FE763A64F5E54FD6')
+sha256sums=('SKIP' 'SKIP')
+";
+        let pkgbuild = parse(script).unwrap();
+        assert_eq!(
+            pkgbuild.validpgpkeys,
+            &["64B13F7117D6E07D661BBCE0FE763A64F5E54FD6"]
+        );
+    }
+
+    #[test]
+    fn test_split_package() {
+        init();
+
+        let script = br#"pkgbase=linux
+pkgname=("$pkgbase" "$pkgbase-headers" "$pkgbase-docs")
+pkgver=6.9.1.arch1
+package_linux() {
+    true
+}
+package_linux-headers() {
+    true
+}
+package_linux-docs() {
+    true
+}
+"#;
+        let pkgbuild = parse(script).unwrap();
+        assert_eq!(pkgbuild.base(), Some("linux"));
+        assert_eq!(pkgbuild.pkgname, &["linux", "linux-headers", "linux-docs"]);
+        pkgbuild.check_package("linux", "linux-headers").unwrap();
+        pkgbuild.check_package("linux", "linux-docs").unwrap();
+        assert!(pkgbuild.check_package("linux", "linux-lts").is_err());
+        assert!(pkgbuild.check_package("linux-lts", "linux").is_err());
+
+        let script = br#"pkgbase=linux
+pkgname=("$pkgbase" "$pkgbase-headers")
+package_linux() {
+    true
+}
+"#;
+        assert!(parse(script).is_err());
+    }
+
+    #[test]
+    fn test_pkgbase_default() {
+        let pkgbuild = Pkgbuild {
+            pkgname: vec!["cmatrix".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(pkgbuild.base(), Some("cmatrix"));
+        pkgbuild.check_package("cmatrix", "cmatrix").unwrap();
+        assert!(Pkgbuild::default()
+            .check_package("cmatrix", "cmatrix")
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_source_vcs() {
+        assert_eq!(
+            Source::parse("git+https://github.com/kpcyrd/backseat-signed.git#tag=v0.1.0"),
+            Source {
+                filename: "backseat-signed".to_string(),
+                url: "https://github.com/kpcyrd/backseat-signed.git".to_string(),
+                vcs: Some("git".to_string()),
+                fragment: Some("tag=v0.1.0".to_string()),
+            }
+        );
+        assert_eq!(
+            Source::parse("foo::git://example.com/foo.git#commit=abcd"),
+            Source {
+                filename: "foo".to_string(),
+                url: "git://example.com/foo.git".to_string(),
+                vcs: Some("git".to_string()),
+                fragment: Some("commit=abcd".to_string()),
+            }
+        );
+        assert_eq!(
+            Source::parse("svn+https://svn.example.com/trunk/"),
+            Source {
+                filename: "trunk".to_string(),
+                url: "https://svn.example.com/trunk/".to_string(),
+                vcs: Some("svn".to_string()),
+                fragment: None,
             }
         );
     }
@@ -0,0 +1,161 @@
+use crate::buildinfo::set_once;
+use crate::errors::*;
+use bstr::ByteSlice;
+use std::str;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ArchLinuxPkginfo {
+    pub pkgname: String,
+    pub pkgbase: Option<String>,
+    /// The full version of the package, including epoch and pkgrel
+    pub pkgver: String,
+    pub pkgdesc: Option<String>,
+    pub url: Option<String>,
+    pub arch: String,
+    pub packager: String,
+    pub builddate: Option<u64>,
+    pub size: u64,
+    pub license: Vec<String>,
+    pub depends: Vec<String>,
+}
+
+pub fn parse_archlinux(bytes: &[u8]) -> Result<ArchLinuxPkginfo> {
+    let mut pkgname = None;
+    let mut pkgbase = None;
+    let mut pkgver = None;
+    let mut pkgdesc = None;
+    let mut url = None;
+    let mut arch = None;
+    let mut packager = None;
+    let mut builddate = None;
+    let mut size = None;
+    let mut license = Vec::new();
+    let mut depends = Vec::new();
+
+    for line in bytes.lines() {
+        trace!("Line in .PKGINFO: {:?}", bstr::BStr::new(line));
+        let line = str::from_utf8(line)?;
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once(" = ")
+            .with_context(|| anyhow!("Malformed line in .PKGINFO: {line:?}"))?;
+
+        match key {
+            "pkgname" => set_once(&mut pkgname, key, value)?,
+            "pkgbase" => set_once(&mut pkgbase, key, value)?,
+            "pkgver" => set_once(&mut pkgver, key, value)?,
+            "pkgdesc" => set_once(&mut pkgdesc, key, value)?,
+            "url" => set_once(&mut url, key, value)?,
+            "arch" => set_once(&mut arch, key, value)?,
+            "packager" => set_once(&mut packager, key, value)?,
+            "builddate" => set_once(&mut builddate, key, value)?,
+            "size" => set_once(&mut size, key, value)?,
+            "license" => license.push(value.to_string()),
+            "depend" => depends.push(value.to_string()),
+            _ => trace!("Ignoring key in .PKGINFO: {key:?}"),
+        }
+    }
+
+    let builddate = builddate
+        .map(|value| {
+            value
+                .parse()
+                .with_context(|| anyhow!("Failed to parse builddate as number: {value:?}"))
+        })
+        .transpose()?;
+    let size = size.context("Failed to find size in .PKGINFO")?;
+    let size = size
+        .parse()
+        .with_context(|| anyhow!("Failed to parse size as number: {size:?}"))?;
+
+    Ok(ArchLinuxPkginfo {
+        pkgname: pkgname.context("Failed to find pkgname in .PKGINFO")?,
+        pkgbase,
+        pkgver: pkgver.context("Failed to find pkgver in .PKGINFO")?,
+        pkgdesc,
+        url,
+        arch: arch.context("Failed to find arch in .PKGINFO")?,
+        packager: packager.context("Failed to find packager in .PKGINFO")?,
+        builddate,
+        size,
+        license,
+        depends,
+    })
+}
+
+/// Extract the email address from a packager string like `Name <user@example.com>`
+pub fn packager_email(packager: &str) -> Option<&str> {
+    let (_, email) = packager.rsplit_once('<')?;
+    let email = email.strip_suffix('>')?;
+    Some(email)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pkginfo() {
+        let pkginfo = parse_archlinux(
+            b"# Generated by makepkg 6.0.2
+# using fakeroot version 1.31
+pkgname = cmatrix
+pkgbase = cmatrix
+pkgver = 2.0-3
+pkgdesc = A curses-based scrolling 'Matrix'-like screen
+url = https://github.com/abishekvashok/cmatrix
+builddate = 1686262344
+packager = kpcyrd <kpcyrd@archlinux.org>
+size = 43573
+arch = x86_64
+license = GPL3
+depend = ncurses
+makedepend = cmake
+",
+        )
+        .unwrap();
+        assert_eq!(
+            pkginfo,
+            ArchLinuxPkginfo {
+                pkgname: "cmatrix".to_string(),
+                pkgbase: Some("cmatrix".to_string()),
+                pkgver: "2.0-3".to_string(),
+                pkgdesc: Some("A curses-based scrolling 'Matrix'-like screen".to_string()),
+                url: Some("https://github.com/abishekvashok/cmatrix".to_string()),
+                arch: "x86_64".to_string(),
+                packager: "kpcyrd <kpcyrd@archlinux.org>".to_string(),
+                builddate: Some(1686262344),
+                size: 43573,
+                license: vec!["GPL3".to_string()],
+                depends: vec!["ncurses".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_pkginfo_duplicate_key() {
+        let err = parse_archlinux(
+            b"pkgname = cmatrix
+pkgname = cmatrix-evil
+pkgver = 2.0-3
+packager = kpcyrd <kpcyrd@archlinux.org>
+size = 43573
+arch = x86_64
+",
+        );
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn test_packager_email() {
+        assert_eq!(
+            packager_email("kpcyrd <kpcyrd@archlinux.org>"),
+            Some("kpcyrd@archlinux.org")
+        );
+        assert_eq!(packager_email("Unknown Packager"), None);
+    }
+}
@@ -1,24 +1,38 @@
 use crate::apt;
 use crate::buildinfo;
 use crate::chksums;
-use crate::compression;
+use crate::compression::{self, Limits};
+use crate::copyright::Copyright;
 use crate::errors::*;
+use crate::git;
+use crate::mirror;
+use crate::mtree;
 use crate::pgp;
 use crate::pkgbuild;
+use crate::srcinfo;
+use crate::syncdb;
+use crate::tree;
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 use tokio::fs;
 
-pub async fn run(plumbing: Plumbing) -> Result<()> {
+pub async fn run(plumbing: Plumbing, limits: &Limits) -> Result<()> {
     match plumbing {
-        Plumbing::ArchlinuxPkgFromSig(args) => args.run().await,
-        Plumbing::ArchlinuxPkgbuildFromPkg(args) => args.run().await,
+        Plumbing::ArchlinuxPkgFromSig(args) => args.run(limits).await,
+        Plumbing::ArchlinuxPkgFromDb(args) => args.run(limits).await,
+        Plumbing::ArchlinuxPkgbuildFromPkg(args) => args.run(limits).await,
+        Plumbing::ArchlinuxPkgbuildFromGitTag(args) => args.run().await,
+        Plumbing::ArchlinuxContentsFromPkg(args) => args.run(limits).await,
         Plumbing::ArchlinuxFileFromPkgbuild(args) => args.run().await,
+        Plumbing::ArchlinuxFileFromMirror(args) => args.run(limits).await,
+        Plumbing::ArchlinuxFileFromUpstreamSig(args) => args.run().await,
         // Plumbing::ArchlinuxGitFromPkgbuild(args) => args.run(),
         // Plumbing::GitFromTarball(args) => args.run(),
         Plumbing::PgpVerify(args) => args.run().await,
-        Plumbing::DebianSourcesFromRelease(args) => args.run().await,
-        Plumbing::DebianTarballFromSources(args) => args.run().await,
+        Plumbing::DebianSourcesFromRelease(args) => args.run(limits).await,
+        Plumbing::DebianSourcesFromMirror(args) => args.run().await,
+        Plumbing::DebianTarballFromSources(args) => args.run(limits).await,
+        Plumbing::DebianTarballFromMirror(args) => args.run(limits).await,
     }
 }
 
@@ -26,13 +40,20 @@ pub async fn run(plumbing: Plumbing) -> Result<()> {
 #[derive(Debug, Subcommand)]
 pub enum Plumbing {
     ArchlinuxPkgFromSig(ArchlinuxPkgFromSig),
+    ArchlinuxPkgFromDb(ArchlinuxPkgFromDb),
     ArchlinuxPkgbuildFromPkg(ArchlinuxPkgbuildFromPkg),
+    ArchlinuxPkgbuildFromGitTag(ArchlinuxPkgbuildFromGitTag),
+    ArchlinuxContentsFromPkg(ArchlinuxContentsFromPkg),
     ArchlinuxFileFromPkgbuild(ArchlinuxFileFromPkgbuild),
+    ArchlinuxFileFromMirror(ArchlinuxFileFromMirror),
+    ArchlinuxFileFromUpstreamSig(ArchlinuxFileFromUpstreamSig),
     // ArchlinuxGitFromPkgbuild(ArchlinuxGitFromPkgbuild),
     // GitFromTarball(GitFromTarball),
     PgpVerify(PgpVerify),
     DebianSourcesFromRelease(DebianSourcesFromRelease),
+    DebianSourcesFromMirror(DebianSourcesFromMirror),
     DebianTarballFromSources(DebianTarballFromSources),
+    DebianTarballFromMirror(DebianTarballFromMirror),
 }
 
 /// Authenticate an Arch Linux package by signature and keyring
@@ -42,11 +63,14 @@ pub struct ArchlinuxPkgFromSig {
     pub keyring: PathBuf,
     #[arg(long)]
     pub sig: PathBuf,
+    /// Also check .PKGINFO and .BUILDINFO agree with each other and with the signing key
+    #[arg(long)]
+    pub check_metadata: bool,
     pub file: PathBuf,
 }
 
 impl ArchlinuxPkgFromSig {
-    async fn run(&self) -> Result<()> {
+    async fn run(&self, limits: &Limits) -> Result<()> {
         info!("Loading keyring from {:?}", self.keyring);
         let keyring = fs::read(&self.keyring).await?;
         let keyring = pgp::keyring(&keyring)?;
@@ -59,9 +83,76 @@ impl ArchlinuxPkgFromSig {
         info!("Loading package from {:?}", self.file);
         let msg = fs::read(&self.file).await?;
 
-        pgp::verify(&keyring, &sig, &msg)?;
+        let fingerprint = pgp::verify(&keyring, &sig, &msg)?;
         info!("Package verified successfully");
 
+        if self.check_metadata {
+            info!("Parsing .PKGINFO and .BUILDINFO from package");
+            let metadata = buildinfo::metadata_from_archlinux_pkg(&msg, limits)?;
+            metadata.check_consistency()?;
+
+            let user_ids = pgp::user_ids(&keyring, &fingerprint)?;
+            metadata.check_packager(&user_ids)?;
+            info!(
+                "Package metadata verified successfully (packager={:?})",
+                metadata.pkginfo.packager
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Authenticate an Arch Linux package through a pacman sync database (like core.db)
+#[derive(Debug, Parser)]
+pub struct ArchlinuxPkgFromDb {
+    #[arg(long)]
+    pub keyring: PathBuf,
+    #[arg(long)]
+    pub db: PathBuf,
+    /// The detached signature of the sync database, if the repository is signed
+    #[arg(long)]
+    pub db_sig: Option<PathBuf>,
+    pub file: PathBuf,
+}
+
+impl ArchlinuxPkgFromDb {
+    async fn run(&self, limits: &Limits) -> Result<()> {
+        info!("Loading keyring from {:?}", self.keyring);
+        let keyring = fs::read(&self.keyring).await?;
+        let keyring = pgp::keyring(&keyring)?;
+        info!("Loaded {} public keys", keyring.len());
+
+        info!("Loading sync database from {:?}", self.db);
+        let db = fs::read(&self.db).await?;
+
+        if let Some(db_sig) = &self.db_sig {
+            info!("Loading sync database signature from {db_sig:?}");
+            let sig = fs::read(db_sig).await?;
+            let sig = pgp::signature(&sig)?;
+            pgp::verify(&keyring, &sig, &db)?;
+            info!("Sync database verified successfully");
+        }
+        let db = syncdb::SyncDb::parse(&db, limits)?;
+
+        info!("Loading package from {:?}", self.file);
+        let pkg = fs::read(&self.file).await?;
+
+        debug!("Checking hash...");
+        let sha256 = chksums::sha256(&pkg);
+        let entry = db.find_pkg_by_sha256(&sha256, pkg.len() as u64)?;
+        info!("Found package in sync database: {:?}", entry.filename);
+
+        if let Some(sig) = entry.pgpsig()? {
+            let sig = pgp::signature(&sig)?;
+            pgp::verify(&keyring, &sig, &pkg)?;
+            info!("Package verified successfully with embedded %PGPSIG%");
+        } else if self.db_sig.is_some() {
+            info!("Package verified successfully through sync database signature");
+        } else {
+            bail!("Package has no %PGPSIG% and the sync database signature was not provided");
+        }
+
         Ok(())
     }
 }
@@ -75,7 +166,7 @@ pub struct ArchlinuxPkgbuildFromPkg {
 }
 
 impl ArchlinuxPkgbuildFromPkg {
-    async fn run(&self) -> Result<()> {
+    async fn run(&self, limits: &Limits) -> Result<()> {
         info!("Loading PKGBUILD from {:?}", self.pkgbuild);
         let pkgbuild = fs::read(&self.pkgbuild).await?;
 
@@ -83,26 +174,127 @@ impl ArchlinuxPkgbuildFromPkg {
         let pkg = fs::read(&self.pkg).await?;
 
         info!("Parsing .BUILDINFO from package");
-        let buildinfo = buildinfo::from_archlinux_pkg(&pkg)?;
-        let pkgbuild_sha256sum = buildinfo.pkgbuild_sha256sum;
+        let buildinfo = buildinfo::from_archlinux_pkg(&pkg, limits)?;
+        let pkgbuild_sha256sum = &buildinfo.pkgbuild_sha256sum;
 
         debug!("Hashing PKGBUILD");
         let sha256 = chksums::sha256(&pkgbuild);
 
-        if sha256 == pkgbuild_sha256sum {
-            info!("PKGBUILD verified successfully");
-            Ok(())
-        } else {
+        if sha256 != *pkgbuild_sha256sum {
             bail!("PKGBUILD sha256={sha256:?} does not match .BUILDINFO pkgbuild_sha256sum={pkgbuild_sha256sum:?}");
         }
+
+        // the hash is authoritative, the PKGBUILD model is only checked if it can be evaluated
+        match pkgbuild::parse(&pkgbuild) {
+            Ok(pkgbuild) => pkgbuild.check_package(&buildinfo.pkgbase, &buildinfo.pkgname)?,
+            Err(err) => {
+                warn!("Failed to parse PKGBUILD, not checking pkgbase and pkgname: {err:#}")
+            }
+        }
+
+        info!("PKGBUILD verified successfully");
+        Ok(())
     }
 }
 
-/// Authenticate a file referenced by hash from a PKGBUILD
+/// Authenticate a PKGBUILD by a signed tag in the Arch Linux packaging git repository
+#[derive(Debug, Parser)]
+pub struct ArchlinuxPkgbuildFromGitTag {
+    /// Keyring for OpenPGP signed tags
+    #[arg(long, required_unless_present = "allowed_signers")]
+    pub keyring: Option<PathBuf>,
+    /// The allowed signers file for SSH signed tags (see ssh-keygen(1))
+    #[arg(long)]
+    pub allowed_signers: Option<PathBuf>,
+    /// A local clone of the packaging git repository
+    #[arg(long)]
+    pub packaging_repo: PathBuf,
+    /// The version of the package, including pkgrel (e.g. `2.0-3`)
+    #[arg(long)]
+    pub version: String,
+    pub pkgbuild: PathBuf,
+}
+
+impl ArchlinuxPkgbuildFromGitTag {
+    async fn run(&self) -> Result<()> {
+        let name = git::archlinux_tag_name(&self.version);
+        info!("Reading tag {name:?} from {:?}", self.packaging_repo);
+        let tag = git::read_tag(&self.packaging_repo, &name).await?;
+
+        match &tag.signature {
+            Some(git::TagSignature::Pgp(_)) => {
+                let path = self
+                    .keyring
+                    .as_ref()
+                    .context("Tag is signed with OpenPGP, but no keyring was provided")?;
+                info!("Loading keyring from {path:?}");
+                let keyring = fs::read(path).await?;
+                let keyring = pgp::keyring(&keyring)?;
+                info!("Loaded {} public keys", keyring.len());
+
+                let fp = tag.verify_pgp(&keyring)?;
+                info!("Tag verified successfully with key {fp:X}");
+            }
+            Some(git::TagSignature::Ssh(_)) => {
+                let path = self
+                    .allowed_signers
+                    .as_ref()
+                    .context("Tag is signed with SSH, but no allowed signers file was provided")?;
+                let principal = tag.verify_ssh(path).await?;
+                info!("Tag verified successfully for {principal:?}");
+            }
+            None => bail!("Tag {name:?} is not signed"),
+        }
+
+        info!("Reading PKGBUILD from commit {:?}", tag.object);
+        let tagged = git::read_file(&self.packaging_repo, &tag.object, "PKGBUILD").await?;
+
+        info!("Loading PKGBUILD from {:?}", self.pkgbuild);
+        let pkgbuild = fs::read(&self.pkgbuild).await?;
+
+        if pkgbuild != tagged {
+            bail!(
+                "PKGBUILD sha256={:?} does not match the one in tag {name:?} sha256={:?}",
+                chksums::sha256(&pkgbuild),
+                chksums::sha256(&tagged)
+            );
+        }
+
+        info!("PKGBUILD verified successfully");
+        Ok(())
+    }
+}
+
+/// Authenticate the contents of an Arch Linux package with its embedded .MTREE
+#[derive(Debug, Parser)]
+pub struct ArchlinuxContentsFromPkg {
+    pub pkg: PathBuf,
+}
+
+impl ArchlinuxContentsFromPkg {
+    async fn run(&self, limits: &Limits) -> Result<()> {
+        info!("Loading package from {:?}", self.pkg);
+        let pkg = fs::read(&self.pkg).await?;
+
+        info!("Verifying package contents with .MTREE");
+        let num = mtree::verify_archlinux_pkg(&pkg, limits)?;
+
+        info!("Package contents verified successfully ({num} entries)");
+        Ok(())
+    }
+}
+
+/// Authenticate a file referenced by hash from a PKGBUILD (or .SRCINFO)
 #[derive(Debug, Parser)]
 pub struct ArchlinuxFileFromPkgbuild {
     #[arg(long)]
     pub pkgbuild: PathBuf,
+    /// Also check the .SRCINFO agrees with the PKGBUILD on sources and checksums
+    #[arg(long)]
+    pub srcinfo: Option<PathBuf>,
+    /// Only consider the source arrays relevant for this architecture (`pkgarch` in .BUILDINFO)
+    #[arg(long)]
+    pub arch: Option<String>,
     pub file: PathBuf,
 }
 
@@ -112,11 +304,104 @@ impl ArchlinuxFileFromPkgbuild {
         let pkgbuild = fs::read(&self.pkgbuild).await?;
         let pkgbuild = pkgbuild::parse(&pkgbuild)?;
 
+        // .SRCINFO is not authenticated by itself, it's only trusted as far as it agrees with PKGBUILD
+        if let Some(path) = &self.srcinfo {
+            info!("Loading .SRCINFO from {path:?}");
+            let srcinfo = fs::read(path).await?;
+            let srcinfo = srcinfo::parse(&srcinfo)?;
+
+            info!("Checking .SRCINFO matches PKGBUILD");
+            srcinfo::cross_check(&srcinfo, &pkgbuild)?;
+        }
+
         info!("Loading file from {:?}", self.file);
         let content = fs::read(&self.file).await?;
 
         info!("Checking hashes");
-        pkgbuild.has_artifact_by_checksum(&content)?;
+        pkgbuild.has_artifact_by_checksum(&content, self.arch.as_deref())?;
+
+        info!("File verified successfully");
+        Ok(())
+    }
+}
+
+/// Authenticate a file by resolving package, signature and PKGBUILD from a local Arch Linux mirror
+#[derive(Debug, Parser)]
+pub struct ArchlinuxFileFromMirror {
+    #[arg(long)]
+    pub keyring: PathBuf,
+    /// The root directory of the mirror or archive
+    #[arg(long)]
+    pub mirror: PathBuf,
+    /// A local checkout of the packaging git repository
+    #[arg(long)]
+    pub packaging_repo: PathBuf,
+    #[arg(long)]
+    pub name: String,
+    /// The version of the package, including pkgrel (e.g. `2.0-3`)
+    #[arg(long)]
+    pub version: String,
+    #[arg(long)]
+    pub arch: Option<String>,
+    pub file: PathBuf,
+}
+
+impl ArchlinuxFileFromMirror {
+    async fn run(&self, limits: &Limits) -> Result<()> {
+        info!("Loading keyring from {:?}", self.keyring);
+        let keyring = fs::read(&self.keyring).await?;
+        let keyring = pgp::keyring(&keyring)?;
+        info!("Loaded {} public keys", keyring.len());
+
+        let mirror = mirror::ArchMirror::new(&self.mirror);
+        let pkg = mirror
+            .find_package(&self.name, &self.version, self.arch.as_deref())
+            .await?;
+
+        info!("Loading signature from {:?}", pkg.sig);
+        let sig = fs::read(&pkg.sig)
+            .await
+            .with_context(|| anyhow!("Failed to load signature from {:?}", pkg.sig))?;
+        let sig = pgp::signature(&sig)?;
+
+        info!("Loading package from {:?}", pkg.path);
+        let pkg = fs::read(&pkg.path).await?;
+
+        pgp::verify(&keyring, &sig, &pkg)?;
+        info!("Package verified successfully");
+
+        info!("Parsing .BUILDINFO from package");
+        let buildinfo = buildinfo::from_archlinux_pkg(&pkg, limits)?;
+
+        let path = self.packaging_repo.join("PKGBUILD");
+        info!("Loading PKGBUILD from {path:?}");
+        let pkgbuild = fs::read(&path).await?;
+
+        let sha256 = chksums::sha256(&pkgbuild);
+        if sha256 != buildinfo.pkgbuild_sha256sum {
+            bail!(
+                "PKGBUILD sha256={sha256:?} does not match .BUILDINFO pkgbuild_sha256sum={:?}",
+                buildinfo.pkgbuild_sha256sum
+            );
+        }
+        let pkgbuild = pkgbuild::parse(&pkgbuild)?;
+        pkgbuild.check_package(&buildinfo.pkgbase, &buildinfo.pkgname)?;
+        info!("PKGBUILD verified successfully");
+
+        // .SRCINFO is not covered by .BUILDINFO, but if present it needs to agree with PKGBUILD
+        let path = self.packaging_repo.join(".SRCINFO");
+        if fs::try_exists(&path).await? {
+            info!("Loading .SRCINFO from {path:?}");
+            let srcinfo = fs::read(&path).await?;
+            let srcinfo = srcinfo::parse(&srcinfo)?;
+            srcinfo::cross_check(&srcinfo, &pkgbuild)?;
+        }
+
+        info!("Loading file from {:?}", self.file);
+        let content = fs::read(&self.file).await?;
+
+        info!("Checking hashes");
+        pkgbuild.has_artifact_by_checksum(&content, Some(&buildinfo.pkgarch))?;
 
         info!("File verified successfully");
         Ok(())
@@ -145,6 +430,49 @@ impl GitFromTarball {
 }
 */
 
+/// Authenticate a file by its upstream signature, using only keys listed in the PKGBUILD `validpgpkeys=`
+#[derive(Debug, Parser)]
+pub struct ArchlinuxFileFromUpstreamSig {
+    #[arg(long)]
+    pub keyring: PathBuf,
+    #[arg(long)]
+    pub pkgbuild: PathBuf,
+    #[arg(long)]
+    pub sig: PathBuf,
+    pub file: PathBuf,
+}
+
+impl ArchlinuxFileFromUpstreamSig {
+    async fn run(&self) -> Result<()> {
+        info!("Loading PKGBUILD from {:?}", self.pkgbuild);
+        let pkgbuild = fs::read(&self.pkgbuild).await?;
+        let pkgbuild = pkgbuild::parse(&pkgbuild)?;
+        if pkgbuild.validpgpkeys.is_empty() {
+            bail!("PKGBUILD does not list any validpgpkeys");
+        }
+
+        info!("Loading keyring from {:?}", self.keyring);
+        let keyring = fs::read(&self.keyring).await?;
+        let keyring = pgp::keyring(&keyring)?;
+        let keyring = pgp::filter_keyring(keyring, &pkgbuild.validpgpkeys)?;
+        info!(
+            "Loaded {} public keys listed in validpgpkeys",
+            keyring.len()
+        );
+
+        info!("Loading signature from {:?}", self.sig);
+        let sig = fs::read(&self.sig).await?;
+        let sig = pgp::signature(&sig)?;
+
+        info!("Loading file from {:?}", self.file);
+        let content = fs::read(&self.file).await?;
+
+        let fp = pgp::verify(&keyring, &sig, &content)?;
+        info!("File verified successfully with upstream key {fp:X}");
+        Ok(())
+    }
+}
+
 /// Authenticate a pgp signed message
 #[derive(Debug, Parser)]
 pub struct PgpVerify {
@@ -185,11 +513,27 @@ pub struct DebianSourcesFromRelease {
     pub sig: PathBuf,
     #[arg(long)]
     pub release: PathBuf,
+    /// The archive component the sources index belongs to
+    #[arg(long, default_value = "main")]
+    pub component: String,
+    /// The path of the sources index in the release file (derived from the component by default)
+    #[arg(long)]
+    pub path: Option<String>,
     pub sources: PathBuf,
 }
 
 impl DebianSourcesFromRelease {
-    async fn run(&self) -> Result<()> {
+    fn release_path(&self) -> String {
+        if let Some(path) = &self.path {
+            path.clone()
+        } else {
+            format!("{}/source/Sources", self.component)
+        }
+    }
+
+    async fn run(&self, limits: &Limits) -> Result<()> {
+        let path = self.release_path();
+
         info!("Loading keyring from {:?}", self.keyring);
         let keyring = fs::read(&self.keyring)
             .await
@@ -219,15 +563,49 @@ impl DebianSourcesFromRelease {
         // Parse release, match with sources
         let release = apt::Release::parse(&release)?;
 
-        debug!("Checking hash...");
-        let sha256 = chksums::sha256(&sources);
-        let _sources_entry = release.find_source_entry_by_sha256(&sha256)?;
+        debug!("Checking hash and size of {path:?}...");
+        let _sources_entry = release.find_sources_entry(&path, &sources, limits)?;
 
         info!("Sources index verified successfully");
         Ok(())
     }
 }
 
+/// Authenticate a Debian source index in a local mirror, following `Acquire-By-Hash` paths
+#[derive(Debug, Parser)]
+pub struct DebianSourcesFromMirror {
+    #[arg(long)]
+    pub keyring: PathBuf,
+    /// The root directory of the mirror, containing `dists/`
+    #[arg(long)]
+    pub mirror: PathBuf,
+    #[arg(long)]
+    pub suite: String,
+    /// The archive component the sources index belongs to
+    #[arg(long, default_value = "main")]
+    pub component: String,
+}
+
+impl DebianSourcesFromMirror {
+    async fn run(&self) -> Result<()> {
+        info!("Loading keyring from {:?}", self.keyring);
+        let keyring = fs::read(&self.keyring)
+            .await
+            .with_context(|| anyhow!("Failed to load keyring from {:?}", self.keyring))?;
+        let keyring = pgp::keyring(&keyring)?;
+        info!("Loaded {} public keys", keyring.len());
+
+        let mirror = mirror::DebianMirror::new(&self.mirror);
+        let release = mirror.load_release(&self.suite, &keyring).await?;
+
+        let path = format!("{}/source/Sources", self.component);
+        let sources = mirror.resolve_index(&self.suite, &release, &path).await?;
+
+        info!("Sources index verified successfully: {:?}", sources.path);
+        Ok(())
+    }
+}
+
 /// Authenticate a source tarball from a Debian source index
 #[derive(Debug, Parser)]
 pub struct DebianTarballFromSources {
@@ -239,41 +617,217 @@ pub struct DebianTarballFromSources {
     pub version: Option<String>,
     #[arg(long)]
     pub orig: Option<PathBuf>,
+    /// Compare the files in both tarballs instead of the tar bytes, files may only be removed with `--debian-tarball`
+    #[arg(long, requires = "orig")]
+    pub tree: bool,
+    /// The .debian.tar of the source package, files may only be removed if listed in `Files-Excluded` of debian/copyright
+    #[arg(long, requires = "tree")]
+    pub debian_tarball: Option<PathBuf>,
     pub file: PathBuf,
 }
 
 impl DebianTarballFromSources {
-    async fn run(&self) -> Result<()> {
+    async fn run(&self, limits: &Limits) -> Result<()> {
         info!("Loading sources index from {:?}", self.sources);
         let sources = fs::read(&self.sources).await?;
-        let sources = apt::SourcesIndex::parse(&sources)?;
-
-        info!("Loading file from {:?}", self.file);
-        let content = fs::read(&self.file).await?;
+        let sources = apt::SourcesIndex::parse(&sources, limits)?;
+
+        let debian_tarball = if let Some(path) = &self.debian_tarball {
+            info!("Reading debian/copyright from {path:?}");
+            let bytes = fs::read(path).await?;
+            let copyright = Copyright::from_debian_tarball(&bytes, limits)?;
+            debug!(
+                "Files excluded from Debian .orig.tar: {:?}",
+                copyright.files_excluded
+            );
+            Some((chksums::sha256(&bytes), copyright))
+        } else {
+            None
+        };
+        let copyright = debian_tarball.as_ref().map(|(_, copyright)| copyright);
+
+        info!("Hashing file {:?}", self.file);
+        let sha256 = match &self.orig {
+            Some(orig) if self.tree => {
+                debug!("Reading files from tarball...");
+                let upstream = tree::Tree::from_path(&self.file, limits).await?;
+
+                info!("Reading files from Debian .orig.tar {orig:?}");
+                let (orig, (sha256, _)) = tree::Tree::from_path_hashed(orig, limits).await?;
+
+                // Files-Excluded patterns are matched against the paths in the upstream tarball
+                let top_level = upstream.top_level().map(|top| format!("{top}/"));
+                let upstream = upstream.strip_top_level();
+                let orig = orig.strip_top_level();
+                let diff = tree::diff(&upstream, &orig);
+                if copyright.is_none() {
+                    for path in &diff.removed {
+                        error!("File has been removed from Debian .orig.tar: {path:?}");
+                    }
+                }
+                for path in &diff.added {
+                    error!("File has been added to Debian .orig.tar: {path:?}");
+                }
+                for (path, fields) in &diff.altered {
+                    error!(
+                        "File has been altered in Debian .orig.tar: {path:?} ({})",
+                        fields.join(", ")
+                    );
+                }
+                if !diff.is_subset() {
+                    bail!(
+                        "Debian .orig.tar has {} added and {} altered files",
+                        diff.added.len(),
+                        diff.altered.len()
+                    );
+                }
+
+                if let Some(copyright) = copyright {
+                    let exclusions = tree::check_exclusions(&upstream, &orig, |path| {
+                        let top_level = top_level.as_deref().unwrap_or_default();
+                        copyright.is_excluded(&format!("{top_level}{path}"))
+                    });
+                    for path in &exclusions.kept {
+                        error!("File is listed in Files-Excluded but still in Debian .orig.tar: {path:?}");
+                    }
+                    for path in &exclusions.unexpected {
+                        error!("File has been removed from Debian .orig.tar but is not listed in Files-Excluded: {path:?}");
+                    }
+                    if !exclusions.is_empty() {
+                        bail!(
+                            "Debian .orig.tar does not match Files-Excluded ({} kept, {} unexpectedly removed)",
+                            exclusions.kept.len(),
+                            exclusions.unexpected.len()
+                        );
+                    }
+                } else if !diff.is_identical() {
+                    bail!(
+                        "Debian .orig.tar has {} removed files, use --debian-tarball to check them against Files-Excluded",
+                        diff.removed.len()
+                    );
+                }
+                info!(
+                    "Files in Debian .orig.tar match tarball ({} removed)",
+                    diff.removed.len()
+                );
+
+                sha256
+            }
+            Some(orig) => {
+                debug!("Decompressing file...");
+                let content = compression::sha256_decompressed(&self.file, limits).await?;
 
-        let sha256 = if let Some(orig) = &self.orig {
-            debug!("Decompressing file...");
-            let content = compression::decompress(&content)?;
+                info!("Hashing Debian .orig.tar from {orig:?}");
+                let ((sha256, _), orig) =
+                    compression::sha256_file_and_decompressed(orig, limits).await?;
 
-            info!("Loading Debian .orig.tar from {orig:?}");
-            let orig = fs::read(orig).await?;
-            let sha256 = chksums::sha256(&orig);
-            let orig = compression::decompress(&orig)?;
+                if orig != content {
+                    bail!("Decompressed file does match match decompressed Debian .orig.tar");
+                }
 
-            if orig != content {
-                bail!("Decompressed file does match match decompressed Debian .orig.tar");
+                sha256
+            }
+            None => {
+                let (sha256, _) = chksums::sha256_file(&self.file).await?;
+                sha256
             }
-
-            sha256
-        } else {
-            chksums::sha256(&content)
         };
 
         info!("Searching in index...");
-        let _source_pkg =
+        let source_pkg =
             sources.find_pkg_by_sha256(self.name.as_deref(), self.version.as_deref(), &sha256)?;
 
+        if let Some((sha256, _)) = &debian_tarball {
+            // debian/copyright is only trusted if it belongs to the same source package
+            if !source_pkg
+                .checksums_sha256
+                .iter()
+                .any(|entry| entry.hash == *sha256)
+            {
+                bail!(
+                    "Debian tarball is not part of source package {:?}",
+                    source_pkg.package
+                );
+            }
+            debug!(
+                "Debian tarball is part of source package {:?}",
+                source_pkg.package
+            );
+        }
+
         info!("File verified successfully");
         Ok(())
     }
 }
+
+/// Authenticate a source tarball by resolving all files of a source package in a local Debian mirror
+#[derive(Debug, Parser)]
+pub struct DebianTarballFromMirror {
+    #[arg(long)]
+    pub keyring: PathBuf,
+    /// The root directory of the mirror, containing `dists/` and `pool/`
+    #[arg(long)]
+    pub mirror: PathBuf,
+    #[arg(long)]
+    pub suite: String,
+    /// The archive component the source package belongs to
+    #[arg(long, default_value = "main")]
+    pub component: String,
+    #[arg(long)]
+    pub name: String,
+    #[arg(long)]
+    pub version: Option<String>,
+    pub file: PathBuf,
+}
+
+impl DebianTarballFromMirror {
+    async fn run(&self, limits: &Limits) -> Result<()> {
+        info!("Loading keyring from {:?}", self.keyring);
+        let keyring = fs::read(&self.keyring)
+            .await
+            .with_context(|| anyhow!("Failed to load keyring from {:?}", self.keyring))?;
+        let keyring = pgp::keyring(&keyring)?;
+        info!("Loaded {} public keys", keyring.len());
+
+        let mirror = mirror::DebianMirror::new(&self.mirror);
+        let release = mirror.load_release(&self.suite, &keyring).await?;
+
+        let path = format!("{}/source/Sources", self.component);
+        let sources = mirror.resolve_index(&self.suite, &release, &path).await?;
+        let sources = apt::SourcesIndex::parse(&sources.bytes, limits)?;
+
+        let pkg = sources.find_pkg(&self.name, self.version.as_deref())?;
+        info!(
+            "Found source package {:?} (version={:?})",
+            pkg.package, pkg.version
+        );
+        let origs = pkg
+            .checksums_sha256
+            .iter()
+            .filter(|entry| apt::is_orig_tarball(&entry.filename))
+            .collect::<Vec<_>>();
+
+        info!("Hashing file {:?}", self.file);
+        let (sha256, _) = chksums::sha256_file(&self.file).await?;
+        if let Some(orig) = origs.iter().find(|orig| orig.hash == sha256) {
+            info!("File matches {:?}", orig.filename);
+            info!("File verified successfully");
+            return Ok(());
+        }
+
+        debug!("Decompressing file...");
+        let content = compression::sha256_decompressed(&self.file, limits).await?;
+        for orig in mirror.resolve_orig_tarballs(pkg, limits).await? {
+            if orig.decompressed == content {
+                info!("Decompressed file matches decompressed {:?}", orig.path);
+                info!("File verified successfully");
+                return Ok(());
+            }
+        }
+
+        bail!(
+            "File does not match any .orig.tar of {:?} in mirror",
+            pkg.package
+        )
+    }
+}
@@ -0,0 +1,789 @@
+//! Restricted, side-effect-free evaluation of shell words
+//!
+//! Only variable assignments and parameter expansion are supported, anything that would
+//! require executing code (like command substitution) is rejected.
+
+use crate::errors::*;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Var {
+    Scalar(String),
+    Array(Vec<String>),
+}
+
+impl Var {
+    /// The value as it would be used in a scalar context
+    pub fn as_scalar(&self) -> String {
+        match self {
+            Var::Scalar(value) => value.clone(),
+            Var::Array(values) => values.first().cloned().unwrap_or_default(),
+        }
+    }
+
+    pub fn into_array(self) -> Vec<String> {
+        match self {
+            Var::Scalar(value) => vec![value],
+            Var::Array(values) => values,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Env {
+    /// Variables that have been assigned something we couldn't evaluate hold the reason
+    vars: HashMap<String, std::result::Result<Var, String>>,
+}
+
+impl Env {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Var> {
+        match self.vars.get(name) {
+            Some(Ok(var)) => Ok(var),
+            Some(Err(err)) => bail!("Variable {name:?} could not be evaluated: {err}"),
+            None => bail!("Variable {name:?} is not defined"),
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.vars.contains_key(name)
+    }
+
+    /// The names of all variables that have been assigned, in no particular order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.vars.keys().map(String::as_str)
+    }
+
+    /// Record an assignment, `append` is used for bash-style `+=` assignments
+    pub fn assign(&mut self, name: &str, append: bool, value: Result<Var>) {
+        let value = match value {
+            Ok(value) => value,
+            Err(err) => {
+                debug!("Failed to evaluate assignment to {name:?}: {err:#}");
+                self.vars.insert(name.to_string(), Err(format!("{err:#}")));
+                return;
+            }
+        };
+
+        let value = match (append, self.vars.remove(name)) {
+            (true, Some(Ok(Var::Array(mut values)))) => {
+                values.extend(value.into_array());
+                Ok(Var::Array(values))
+            }
+            (true, Some(Ok(Var::Scalar(mut prev)))) => match value {
+                Var::Scalar(value) => {
+                    prev.push_str(&value);
+                    Ok(Var::Scalar(prev))
+                }
+                Var::Array(values) => {
+                    let mut array = vec![prev];
+                    array.extend(values);
+                    Ok(Var::Array(array))
+                }
+            },
+            (true, Some(Err(err))) => Err(err),
+            (_, _) => Ok(value),
+        };
+        self.vars.insert(name.to_string(), value);
+    }
+
+    /// Expand the source code of a single shell word into a string
+    pub fn expand(&self, word: &str) -> Result<String> {
+        let chars = word.chars().collect::<Vec<_>>();
+        let mut out = String::new();
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '\'' => {
+                    let end = find(&chars, i + 1, '\'')
+                        .with_context(|| anyhow!("Unterminated single quote: {word:?}"))?;
+                    out.extend(&chars[i + 1..end]);
+                    i = end + 1;
+                }
+                '"' => {
+                    i += 1;
+                    loop {
+                        match chars.get(i) {
+                            None => bail!("Unterminated double quote: {word:?}"),
+                            Some('"') => break,
+                            Some('\\') => {
+                                match chars.get(i + 1) {
+                                    Some(c @ ('$' | '`' | '"' | '\\')) => out.push(*c),
+                                    Some('\n') => (),
+                                    Some(c) => {
+                                        out.push('\\');
+                                        out.push(*c);
+                                    }
+                                    None => bail!("Unterminated double quote: {word:?}"),
+                                }
+                                i += 2;
+                            }
+                            Some('$') => i = self.expand_dollar(&chars, i, &mut out)?,
+                            Some('`') => bail!("Command substitution is not supported: {word:?}"),
+                            Some(c) => {
+                                out.push(*c);
+                                i += 1;
+                            }
+                        }
+                    }
+                    i += 1;
+                }
+                '\\' => {
+                    if let Some(c) = chars.get(i + 1) {
+                        if *c != '\n' {
+                            out.push(*c);
+                        }
+                    }
+                    i += 2;
+                }
+                '$' => i = self.expand_dollar(&chars, i, &mut out)?,
+                '`' => bail!("Command substitution is not supported: {word:?}"),
+                c => {
+                    out.push(c);
+                    i += 1;
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// Expand the source code of a word in array context, like an element of `name=(...)`
+    ///
+    /// `"${name[@]}"` expands to one word per element, other expansions of all elements would need
+    /// word splitting and are rejected instead of silently changing the number of elements.
+    pub fn expand_words(&self, word: &str) -> Result<Vec<String>> {
+        let (inner, quoted) = match word.strip_prefix('"').and_then(|w| w.strip_suffix('"')) {
+            Some(inner) => (inner, true),
+            None => (word, false),
+        };
+        if let Some(name) = inner
+            .strip_prefix("${")
+            .and_then(|w| w.strip_suffix("[@]}"))
+            .filter(|name| is_name(name))
+        {
+            let values = self.get(name)?.clone().into_array();
+            if quoted {
+                return Ok(values);
+            }
+            return Ok(values
+                .iter()
+                .flat_map(|value| value.split_whitespace())
+                .map(String::from)
+                .collect());
+        }
+
+        // all elements joined into a single word
+        let joined = quoted
+            && inner
+                .strip_prefix("${")
+                .and_then(|w| w.strip_suffix("[*]}"))
+                .is_some_and(is_name);
+        if has_array_expansion(word) && !joined {
+            bail!("Expanding all elements of an array is only supported as \"${{name[@]}}\": {word:?}");
+        }
+        self.expand(word).map(|value| vec![value])
+    }
+
+    /// Expand the `$` expression at position `i`, returns the position after it
+    fn expand_dollar(&self, chars: &[char], i: usize, out: &mut String) -> Result<usize> {
+        match chars.get(i + 1) {
+            Some('{') => {
+                let end =
+                    find_closing_brace(chars, i + 2).context("Unterminated parameter expansion")?;
+                let expr = chars[i + 2..end].iter().collect::<String>();
+                out.push_str(&self.expand_braced(&expr)?);
+                Ok(end + 1)
+            }
+            Some('(') => bail!("Command substitution and arithmetic expansion are not supported"),
+            Some('\'') => bail!("Dollar-single-quotes are not supported"),
+            Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|c| !c.is_ascii_alphanumeric() && *c != '_')
+                    .map_or(chars.len(), |pos| i + 1 + pos);
+                let name = chars[i + 1..end].iter().collect::<String>();
+                out.push_str(&self.get(&name)?.as_scalar());
+                Ok(end)
+            }
+            Some(c) if c.is_ascii_digit() || "@*#?$!-".contains(*c) => {
+                bail!("Special parameter ${c} is not supported")
+            }
+            _ => {
+                out.push('$');
+                Ok(i + 1)
+            }
+        }
+    }
+
+    fn expand_braced(&self, expr: &str) -> Result<String> {
+        if let Some(name) = expr.strip_prefix('#') {
+            if let Some(name) = name
+                .strip_suffix("[@]")
+                .or_else(|| name.strip_suffix("[*]"))
+            {
+                let count = self.get(name)?.clone().into_array().len();
+                return Ok(count.to_string());
+            }
+            let value = self.lookup(name)?;
+            return Ok(value.chars().count().to_string());
+        }
+
+        let end = expr
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(expr.len());
+        let (name, rest) = expr.split_at(end);
+        if name.is_empty() {
+            bail!("Unsupported parameter expansion: ${{{expr}}}");
+        }
+
+        let (name, rest) = if let Some(rest) = rest.strip_prefix('[') {
+            let (index, rest) = rest
+                .split_once(']')
+                .with_context(|| anyhow!("Unterminated array index: ${{{expr}}}"))?;
+            (format!("{name}[{index}]"), rest)
+        } else {
+            (name.to_string(), rest)
+        };
+
+        if rest.is_empty() {
+            return self.lookup(&name);
+        }
+
+        let value = self.lookup(&name);
+        for op in [":-", ":+", "-", "+"] {
+            let Some(word) = rest.strip_prefix(op) else {
+                continue;
+            };
+            let is_set = match &value {
+                Ok(value) => !op.starts_with(':') || !value.is_empty(),
+                Err(_) if !self.contains(name.split('[').next().unwrap_or(&name)) => false,
+                Err(_) => return value,
+            };
+            return if op.ends_with('-') {
+                if is_set {
+                    value
+                } else {
+                    self.expand(word)
+                }
+            } else if is_set {
+                self.expand(word)
+            } else {
+                Ok(String::new())
+            };
+        }
+
+        let value = value?;
+        if let Some(pattern) = rest.strip_prefix("##") {
+            Ok(trim_prefix(&value, &self.expand(pattern)?, true))
+        } else if let Some(pattern) = rest.strip_prefix('#') {
+            Ok(trim_prefix(&value, &self.expand(pattern)?, false))
+        } else if let Some(pattern) = rest.strip_prefix("%%") {
+            Ok(trim_suffix(&value, &self.expand(pattern)?, true))
+        } else if let Some(pattern) = rest.strip_prefix('%') {
+            Ok(trim_suffix(&value, &self.expand(pattern)?, false))
+        } else if let Some(rest) = rest.strip_prefix("//") {
+            let (pattern, replacement) = rest.split_once('/').unwrap_or((rest, ""));
+            let pattern = self.expand(pattern)?;
+            let replacement = self.expand(replacement)?;
+            Ok(replace(&value, &pattern, &replacement, true))
+        } else if let Some(rest) = rest.strip_prefix('/') {
+            let (pattern, replacement) = rest.split_once('/').unwrap_or((rest, ""));
+            let pattern = self.expand(pattern)?;
+            let replacement = self.expand(replacement)?;
+            Ok(replace(&value, &pattern, &replacement, false))
+        } else if let Some(rest) = rest.strip_prefix(':') {
+            let (offset, length) = match rest.split_once(':') {
+                Some((offset, length)) => (offset, Some(length)),
+                None => (rest, None),
+            };
+            let chars = value.chars().collect::<Vec<_>>();
+            let offset = offset
+                .trim()
+                .parse::<usize>()
+                .with_context(|| anyhow!("Unsupported substring offset: ${{{expr}}}"))?
+                .min(chars.len());
+            let end = match length {
+                Some(length) => {
+                    let length = length
+                        .trim()
+                        .parse::<usize>()
+                        .with_context(|| anyhow!("Unsupported substring length: ${{{expr}}}"))?;
+                    (offset + length).min(chars.len())
+                }
+                None => chars.len(),
+            };
+            Ok(chars[offset..end].iter().collect())
+        } else {
+            bail!("Unsupported parameter expansion: ${{{expr}}}")
+        }
+    }
+
+    /// Resolve a variable name, optionally with an array index (`name[0]`, `name[@]`)
+    fn lookup(&self, name: &str) -> Result<String> {
+        let Some((name, index)) = name.strip_suffix(']').and_then(|n| n.split_once('[')) else {
+            return Ok(self.get(name)?.as_scalar());
+        };
+
+        let values = match self.get(name)? {
+            Var::Scalar(value) => vec![value.clone()],
+            Var::Array(values) => values.clone(),
+        };
+        match index {
+            "@" | "*" => Ok(values.join(" ")),
+            index => {
+                let index = index
+                    .parse::<usize>()
+                    .with_context(|| anyhow!("Unsupported array index: {index:?}"))?;
+                Ok(values.get(index).cloned().unwrap_or_default())
+            }
+        }
+    }
+}
+
+fn is_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Check for `${name[@]}` or `${name[*]}` in a word, the length `${#name[@]}` is a single value
+fn has_array_expansion(word: &str) -> bool {
+    word.match_indices("${").any(|(idx, _)| {
+        let expr = &word[idx + 2..];
+        let end = expr
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(expr.len());
+        end > 0 && (expr[end..].starts_with("[@]") || expr[end..].starts_with("[*]"))
+    })
+}
+
+fn find(chars: &[char], start: usize, needle: char) -> Option<usize> {
+    chars[start..]
+        .iter()
+        .position(|c| *c == needle)
+        .map(|pos| start + pos)
+}
+
+fn find_closing_brace(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '\'' => i = find(chars, i + 1, '\'')?,
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => (),
+        }
+        i += 1;
+    }
+    None
+}
+
+const PLACEHOLDER_PREFIX: &str = "${__backseat_expansion_";
+
+/// Braced parameter expansions that have been replaced with placeholders
+///
+/// yash-syntax only understands POSIX parameter expansion, bash-only forms like `${pkgver//./_}`
+/// or `${_commit:0:7}` are hidden from the parser and restored before a word is evaluated.
+#[derive(Debug, Default)]
+pub struct MaskedExpansions {
+    expansions: Vec<String>,
+}
+
+impl MaskedExpansions {
+    /// Replace every `${...}` outside of single quotes and comments with a placeholder
+    pub fn mask(script: &str) -> (String, Self) {
+        let chars = script.chars().collect::<Vec<_>>();
+        let mut masked = Self::default();
+        let mut out = String::new();
+        let mut in_double = false;
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '\\' => {
+                    out.extend(&chars[i..(i + 2).min(chars.len())]);
+                    i += 2;
+                    continue;
+                }
+                '\'' if !in_double => {
+                    let end = find(&chars, i + 1, '\'').unwrap_or(chars.len() - 1);
+                    out.extend(&chars[i..=end]);
+                    i = end + 1;
+                    continue;
+                }
+                '#' if !in_double && (i == 0 || chars[i - 1].is_whitespace()) => {
+                    let end = find(&chars, i, '\n').unwrap_or(chars.len());
+                    out.extend(&chars[i..end]);
+                    i = end;
+                    continue;
+                }
+                '"' => in_double = !in_double,
+                '$' if chars.get(i + 1) == Some(&'{') => {
+                    if let Some(end) = find_closing_brace(&chars, i + 2) {
+                        let idx = masked.expansions.len();
+                        masked.expansions.push(chars[i..=end].iter().collect());
+                        out.push_str(&format!("{PLACEHOLDER_PREFIX}{idx}}}"));
+                        i = end + 1;
+                        continue;
+                    }
+                }
+                _ => (),
+            }
+            out.push(chars[i]);
+            i += 1;
+        }
+        (out, masked)
+    }
+
+    /// Restore the original expansions in the source code of a word
+    pub fn unmask(&self, word: &str) -> String {
+        let mut out = String::new();
+        let mut rest = word;
+        while let Some(pos) = rest.find(PLACEHOLDER_PREFIX) {
+            out.push_str(&rest[..pos]);
+            let after = &rest[pos + PLACEHOLDER_PREFIX.len()..];
+            let original = after.split_once('}').and_then(|(idx, after)| {
+                let original = self.expansions.get(idx.parse::<usize>().ok()?)?;
+                Some((original, after))
+            });
+            match original {
+                Some((original, after)) => {
+                    out.push_str(original);
+                    rest = after;
+                }
+                None => {
+                    out.push_str(PLACEHOLDER_PREFIX);
+                    rest = after;
+                }
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Any,
+    One,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+    Literal(char),
+}
+
+/// A shell glob pattern, supporting `*`, `?`, `[...]` classes and `\` escapes
+#[derive(Debug, PartialEq)]
+pub struct Glob {
+    tokens: Vec<Token>,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Self {
+        let mut tokens = Vec::new();
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            let token = match c {
+                '*' => Token::Any,
+                '?' => Token::One,
+                '\\' => Token::Literal(chars.next().unwrap_or('\\')),
+                '[' => {
+                    let rest = chars.as_str();
+                    let (negated, body) = match rest.strip_prefix(['!', '^']) {
+                        Some(body) => (true, body),
+                        None => (false, rest),
+                    };
+                    // a `]` directly after the opening bracket is part of the class
+                    let Some((end, _)) = body.char_indices().skip(1).find(|(_, c)| *c == ']')
+                    else {
+                        tokens.push(Token::Literal('['));
+                        continue;
+                    };
+
+                    let mut ranges = Vec::new();
+                    let mut class = body[..end].chars();
+                    while let Some(start) = class.next() {
+                        let mut lookahead = class.clone();
+                        match (lookahead.next(), lookahead.next()) {
+                            (Some('-'), Some(stop)) => {
+                                ranges.push((start, stop));
+                                class = lookahead;
+                            }
+                            _ => ranges.push((start, start)),
+                        }
+                    }
+
+                    chars = body[end + 1..].chars();
+                    Token::Class { negated, ranges }
+                }
+                c => Token::Literal(c),
+            };
+            tokens.push(token);
+        }
+        Glob { tokens }
+    }
+
+    /// Match the whole text, `*` also matches `/`
+    ///
+    /// Only the most recent `*` is backtracked, so this runs in `O(pattern * text)`.
+    pub fn matches(&self, text: &[char]) -> bool {
+        let (mut t, mut p) = (0, 0);
+        // position of the last `*` and the text position it currently extends to
+        let mut backtrack = None;
+        while p < text.len() {
+            let matched = match self.tokens.get(t) {
+                Some(Token::Any) => {
+                    backtrack = Some((t, p));
+                    t += 1;
+                    continue;
+                }
+                Some(Token::One) => true,
+                Some(Token::Class { negated, ranges }) => {
+                    let c = text[p];
+                    ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&c)) != *negated
+                }
+                Some(Token::Literal(c)) => text[p] == *c,
+                None => false,
+            };
+
+            if matched {
+                t += 1;
+                p += 1;
+            } else if let Some((star, pos)) = backtrack {
+                t = star + 1;
+                p = pos + 1;
+                backtrack = Some((star, pos + 1));
+            } else {
+                return false;
+            }
+        }
+        self.tokens[t..].iter().all(|token| *token == Token::Any)
+    }
+}
+
+/// Match a shell glob pattern against a string
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    Glob::new(pattern).matches(&text.chars().collect::<Vec<_>>())
+}
+
+fn trim_prefix(value: &str, pattern: &str, longest: bool) -> String {
+    let glob = Glob::new(pattern);
+    let chars = value.chars().collect::<Vec<_>>();
+    let mut ends = (0..=chars.len()).collect::<Vec<_>>();
+    if longest {
+        ends.reverse();
+    }
+    for end in ends {
+        if glob.matches(&chars[..end]) {
+            return chars[end..].iter().collect();
+        }
+    }
+    value.to_string()
+}
+
+fn trim_suffix(value: &str, pattern: &str, longest: bool) -> String {
+    let glob = Glob::new(pattern);
+    let chars = value.chars().collect::<Vec<_>>();
+    let mut starts = (0..=chars.len()).collect::<Vec<_>>();
+    if !longest {
+        starts.reverse();
+    }
+    for start in starts {
+        if glob.matches(&chars[start..]) {
+            return chars[..start].iter().collect();
+        }
+    }
+    value.to_string()
+}
+
+fn replace(value: &str, pattern: &str, replacement: &str, all: bool) -> String {
+    if pattern.is_empty() {
+        return value.to_string();
+    }
+    let glob = Glob::new(pattern);
+    let chars = value.chars().collect::<Vec<_>>();
+
+    let mut out = String::new();
+    let mut i = 0;
+    let mut replaced = false;
+    while i < chars.len() {
+        let end = (!replaced || all)
+            .then(|| {
+                (i + 1..=chars.len())
+                    .rev()
+                    .find(|end| glob.matches(&chars[i..*end]))
+            })
+            .flatten();
+        if let Some(end) = end {
+            out.push_str(replacement);
+            i = end;
+            replaced = true;
+        } else {
+            out.push(chars[i]);
+            i += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env() -> Env {
+        let mut env = Env::new();
+        env.assign("pkgname", false, Ok(Var::Scalar("cmatrix".to_string())));
+        env.assign("pkgver", false, Ok(Var::Scalar("2.0.1".to_string())));
+        env.assign(
+            "_commit",
+            false,
+            Ok(Var::Scalar(
+                "5c082c64a1296859a11bee60b8a2a51a8b2e5a3b".to_string(),
+            )),
+        );
+        env.assign(
+            "arch",
+            false,
+            Ok(Var::Array(vec![
+                "x86_64".to_string(),
+                "aarch64".to_string(),
+            ])),
+        );
+        env
+    }
+
+    #[test]
+    fn test_expand_simple() {
+        let env = env();
+        assert_eq!(
+            env.expand("\"https://example.com/$pkgname-$pkgver.tar.gz\"")
+                .unwrap(),
+            "https://example.com/cmatrix-2.0.1.tar.gz"
+        );
+        assert_eq!(
+            env.expand("$pkgname-${pkgver}.tar.gz::'https://example.com/$pkgname'")
+                .unwrap(),
+            "cmatrix-2.0.1.tar.gz::https://example.com/$pkgname"
+        );
+        assert_eq!(env.expand("\\$pkgname").unwrap(), "$pkgname");
+        assert_eq!(env.expand("${arch[1]}").unwrap(), "aarch64");
+        assert_eq!(env.expand("${arch[@]}").unwrap(), "x86_64 aarch64");
+        assert_eq!(env.expand("${#pkgname}").unwrap(), "7");
+    }
+
+    #[test]
+    fn test_expand_modifiers() {
+        let env = env();
+        assert_eq!(env.expand("${pkgver//./_}").unwrap(), "2_0_1");
+        assert_eq!(env.expand("${pkgver/./_}").unwrap(), "2_0.1");
+        assert_eq!(env.expand("${pkgver%.*}").unwrap(), "2.0");
+        assert_eq!(env.expand("${pkgver%%.*}").unwrap(), "2");
+        assert_eq!(env.expand("${pkgver#*.}").unwrap(), "0.1");
+        assert_eq!(env.expand("${pkgver##*.}").unwrap(), "1");
+        assert_eq!(env.expand("${_commit:0:7}").unwrap(), "5c082c6");
+        assert_eq!(env.expand("${undefined:-fallback}").unwrap(), "fallback");
+        assert_eq!(env.expand("${pkgname:-fallback}").unwrap(), "cmatrix");
+        assert_eq!(env.expand("${pkgname:+set}").unwrap(), "set");
+        assert_eq!(env.expand("${undefined:+set}").unwrap(), "");
+    }
+
+    #[test]
+    fn test_expand_rejects_side_effects() {
+        let env = env();
+        assert!(env.expand("$(curl https://example.com)").is_err());
+        assert!(env.expand("\"`id`\"").is_err());
+        assert!(env.expand("$((1+1))").is_err());
+        assert!(env.expand("$undefined").is_err());
+        assert!(env.expand("$srcdir/foo").is_err());
+    }
+
+    #[test]
+    fn test_expand_words() {
+        let env = env();
+        let words = |word| env.expand_words(word).unwrap();
+        assert_eq!(words("\"${arch[@]}\""), &["x86_64", "aarch64"]);
+        assert_eq!(words("${arch[@]}"), &["x86_64", "aarch64"]);
+        assert_eq!(words("\"${#arch[@]}\""), &["2"]);
+        assert_eq!(words("$pkgname-$pkgver"), &["cmatrix-2.0.1"]);
+        assert_eq!(words("\"${arch[*]}\""), &["x86_64 aarch64"]);
+        assert!(env.expand_words("lib-${arch[@]}.so").is_err());
+    }
+
+    #[test]
+    fn test_assign_append() {
+        let mut env = env();
+        env.assign(
+            "source",
+            false,
+            Ok(Var::Array(vec!["a.tar.gz".to_string()])),
+        );
+        env.assign("source", true, Ok(Var::Array(vec!["b.patch".to_string()])));
+        assert_eq!(
+            env.get("source").unwrap(),
+            &Var::Array(vec!["a.tar.gz".to_string(), "b.patch".to_string()])
+        );
+
+        env.assign("pkgver", true, Ok(Var::Scalar("+r1".to_string())));
+        assert_eq!(env.expand("$pkgver").unwrap(), "2.0.1+r1");
+
+        env.assign("broken", false, Err(anyhow!("Command substitution")));
+        assert!(env.expand("$broken").is_err());
+    }
+
+    #[test]
+    fn test_mask_expansions() {
+        let script = r#"pkgver=2.0.1
+source=("https://example.com/${pkgname}_${pkgver//./_}.patch" '${literal}')
+# comment with ${unterminated
+_short=${_commit:0:7} \${escaped}
+"#;
+        let (masked, expansions) = MaskedExpansions::mask(script);
+        assert_eq!(
+            masked,
+            r#"pkgver=2.0.1
+source=("https://example.com/${__backseat_expansion_0}_${__backseat_expansion_1}.patch" '${literal}')
+# comment with ${unterminated
+_short=${__backseat_expansion_2} \${escaped}
+"#
+        );
+        assert_eq!(expansions.unmask(&masked), script);
+    }
+
+    #[test]
+    fn test_glob_match() {
+        let m = glob_match;
+        assert!(m("*", ""));
+        assert!(m("*", "foo/bar"));
+        assert!(m("*.tar.gz", "foo.tar.gz"));
+        assert!(m("f?o", "foo"));
+        assert!(!m("f?o", "fo"));
+        assert!(m("\\*", "*"));
+        assert!(!m("\\*", "a"));
+        assert!(m("lib[0-9].so", "lib3.so"));
+        assert!(!m("lib[!0-9].so", "lib3.so"));
+        assert!(m("[]]", "]"));
+        assert!(m("a*b*c", "aXbYbZc"));
+        assert!(!m("a*b*c", "aXbYbZ"));
+    }
+
+    #[test]
+    fn test_glob_match_no_backtracking_blowup() {
+        let text = "a".repeat(10_000);
+        assert!(!glob_match("*a*a*a*a*a*b", &text));
+
+        let env = {
+            let mut env = Env::new();
+            env.assign("long", false, Ok(Var::Scalar("a".repeat(200))));
+            env
+        };
+        assert_eq!(
+            env.expand("${long//*a*a*a*a*a*b/x}").unwrap(),
+            "a".repeat(200)
+        );
+    }
+}
@@ -0,0 +1,244 @@
+//! Parser for `.SRCINFO`, the static metadata generated with `makepkg --printsrcinfo`
+
+use crate::errors::*;
+use crate::pkgbuild::{Algorithm, Pkgbuild, Source};
+use std::collections::BTreeSet;
+use std::iter;
+use std::str;
+
+/// Keys that may only be set in the `pkgbase` section, besides sources and checksums
+const PKGBASE_ONLY: &[&str] = &["pkgver", "pkgrel", "epoch", "validpgpkeys"];
+
+/// Split the architecture from a key, e.g. `sha256sums_x86_64`
+fn split_key(key: &str) -> (&str, Option<&str>) {
+    match key.split_once('_') {
+        Some((name, arch)) => (name, Some(arch)),
+        None => (key, None),
+    }
+}
+
+fn algorithm(name: &str) -> Option<Algorithm> {
+    Algorithm::ALL
+        .into_iter()
+        .find(|algorithm| algorithm.array_name() == name)
+}
+
+/// Parse a .SRCINFO file into the same model that is used for PKGBUILDs
+pub fn parse(bytes: &[u8]) -> Result<Pkgbuild> {
+    let text = str::from_utf8(bytes).context(".SRCINFO is not valid utf-8")?;
+
+    let mut pkgbuild = Pkgbuild::default();
+    let mut in_pkgbase = false;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once(" = ")
+            .or_else(|| line.strip_suffix(" =").map(|key| (key, "")))
+            .with_context(|| anyhow!("Malformed line in .SRCINFO: {line:?}"))?;
+        let (key, value) = (key.trim(), value.trim());
+        trace!("Found key in .SRCINFO: {key:?}={value:?}");
+
+        match key {
+            "pkgbase" => {
+                if pkgbuild.pkgbase.is_some() {
+                    bail!("Duplicate pkgbase section in .SRCINFO");
+                }
+                pkgbuild.pkgbase = Some(value.to_string());
+                in_pkgbase = true;
+                continue;
+            }
+            "pkgname" => {
+                if pkgbuild.pkgbase.is_none() {
+                    bail!("Found pkgname section before pkgbase in .SRCINFO");
+                }
+                if pkgbuild.pkgname.iter().any(|name| name == value) {
+                    bail!("Duplicate pkgname section in .SRCINFO: {value:?}");
+                }
+                pkgbuild.pkgname.push(value.to_string());
+                in_pkgbase = false;
+                continue;
+            }
+            _ => (),
+        }
+
+        if pkgbuild.pkgbase.is_none() {
+            bail!("Found key outside of pkgbase section in .SRCINFO: {key:?}");
+        }
+
+        let (name, arch) = split_key(key);
+        let is_source = name == "source" || algorithm(name).is_some();
+        if !in_pkgbase {
+            // sources can't be overridden by split packages
+            if is_source || PKGBASE_ONLY.contains(&name) {
+                bail!("Key can only be set in pkgbase section of .SRCINFO: {key:?}");
+            }
+            continue;
+        }
+
+        if key == "arch" {
+            pkgbuild.arch.push(value.to_string());
+        } else if key == "validpgpkeys" {
+            pkgbuild.validpgpkeys.push(value.to_string());
+        } else if is_source {
+            let sources = match arch {
+                Some(arch) => pkgbuild.arch_sources.entry(arch.to_string()).or_default(),
+                None => &mut pkgbuild.sources,
+            };
+            match algorithm(name) {
+                Some(algorithm) => sources.checksums_mut(algorithm).push(value.to_string()),
+                None => sources.source.push(Source::parse(value)),
+            }
+        }
+    }
+
+    if pkgbuild.pkgbase.is_none() {
+        bail!("Missing pkgbase section in .SRCINFO");
+    }
+    if pkgbuild.pkgname.is_empty() {
+        bail!("Missing pkgname section in .SRCINFO");
+    }
+
+    Ok(pkgbuild)
+}
+
+/// Check .SRCINFO and PKGBUILD agree on sources and checksums for every architecture
+pub fn cross_check(srcinfo: &Pkgbuild, pkgbuild: &Pkgbuild) -> Result<()> {
+    if srcinfo.arch != pkgbuild.arch {
+        bail!(
+            "Architectures in .SRCINFO do not match PKGBUILD: .SRCINFO={:?}, PKGBUILD={:?}",
+            srcinfo.arch,
+            pkgbuild.arch
+        );
+    }
+
+    if srcinfo.validpgpkeys != pkgbuild.validpgpkeys {
+        bail!(
+            "validpgpkeys in .SRCINFO do not match PKGBUILD: .SRCINFO={:?}, PKGBUILD={:?}",
+            srcinfo.validpgpkeys,
+            pkgbuild.validpgpkeys
+        );
+    }
+
+    let archs = srcinfo
+        .arch
+        .iter()
+        .chain(srcinfo.arch_sources.keys())
+        .chain(pkgbuild.arch_sources.keys())
+        .collect::<BTreeSet<_>>();
+
+    for arch in iter::once(None).chain(archs.into_iter().map(Some)) {
+        let (srcinfo, pkgbuild) = match arch {
+            Some(arch) => (
+                srcinfo.sources_for_arch(arch),
+                pkgbuild.sources_for_arch(arch),
+            ),
+            None => (srcinfo.sources.clone(), pkgbuild.sources.clone()),
+        };
+        let arch = arch.map_or("any", |arch| arch.as_str());
+
+        if srcinfo.source != pkgbuild.source {
+            if pkgbuild.source.is_empty() {
+                bail!("Could not compare source= array for {arch:?}, PKGBUILD sources could not be determined");
+            }
+            bail!(
+                "Sources in .SRCINFO do not match PKGBUILD for {arch:?}: .SRCINFO={:?}, PKGBUILD={:?}",
+                srcinfo.source,
+                pkgbuild.source
+            );
+        }
+        for algorithm in Algorithm::ALL {
+            let (a, b) = (srcinfo.checksums(algorithm), pkgbuild.checksums(algorithm));
+            if a != b {
+                bail!("Checksums in {algorithm} do not match for {arch:?}: .SRCINFO={a:?}, PKGBUILD={b:?}");
+            }
+        }
+        debug!("Sources and checksums for {arch:?} match");
+    }
+
+    info!(".SRCINFO and PKGBUILD agree on sources and checksums");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pkgbuild::Sources;
+    use std::collections::BTreeMap;
+
+    const SRCINFO: &str = "pkgbase = cmatrix
+	pkgdesc = A curses-based scrolling 'Matrix'-like screen
+	pkgver = 2.0
+	pkgrel = 3
+	url = https://www.asty.org/cmatrix/
+	arch = x86_64
+	arch = aarch64
+	license = GPL3
+	source = cmatrix-2.0.tar.gz::https://github.com/abishekvashok/cmatrix/archive/v2.0.tar.gz
+	source_aarch64 = https://example.com/fix-aarch64.patch
+	sha256sums = ad93ba39acd383696ab6a9ebbed1259ecf2d3cf9f49d6b97038c66f80749e99a
+	validpgpkeys = 64B13F7117D6E07D661BBCE0FE763A64F5E54FD6
+	sha256sums_aarch64 = 5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03
+
+pkgname = cmatrix
+
+pkgname = cmatrix-docs
+	arch = any
+";
+
+    #[test]
+    fn test_parse_srcinfo() {
+        let srcinfo = parse(SRCINFO.as_bytes()).unwrap();
+        assert_eq!(
+            srcinfo,
+            Pkgbuild {
+                pkgbase: Some("cmatrix".to_string()),
+                pkgname: vec!["cmatrix".to_string(), "cmatrix-docs".to_string()],
+                arch: vec!["x86_64".to_string(), "aarch64".to_string()],
+                validpgpkeys: vec!["64B13F7117D6E07D661BBCE0FE763A64F5E54FD6".to_string()],
+                sources: Sources {
+                    source: vec![Source::parse("cmatrix-2.0.tar.gz::https://github.com/abishekvashok/cmatrix/archive/v2.0.tar.gz")],
+                    sha256sums: vec!["ad93ba39acd383696ab6a9ebbed1259ecf2d3cf9f49d6b97038c66f80749e99a".to_string()],
+                    ..Default::default()
+                },
+                arch_sources: BTreeMap::from([(
+                    "aarch64".to_string(),
+                    Sources {
+                        source: vec![Source::parse("https://example.com/fix-aarch64.patch")],
+                        sha256sums: vec!["5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03".to_string()],
+                        ..Default::default()
+                    }
+                )]),
+            }
+        );
+
+        let m = srcinfo
+            .has_artifact_by_checksum(b"hello\n", Some("aarch64"))
+            .unwrap();
+        assert_eq!(m.index, 1);
+    }
+
+    #[test]
+    fn test_parse_srcinfo_sources_in_package_section() {
+        let srcinfo = format!("{SRCINFO}\tsha256sums = SKIP\n");
+        assert!(parse(srcinfo.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_cross_check() {
+        let srcinfo = parse(SRCINFO.as_bytes()).unwrap();
+        let mut pkgbuild = parse(SRCINFO.as_bytes()).unwrap();
+        cross_check(&srcinfo, &pkgbuild).unwrap();
+
+        pkgbuild
+            .arch_sources
+            .get_mut("aarch64")
+            .unwrap()
+            .sha256sums
+            .push("SKIP".to_string());
+        assert!(cross_check(&srcinfo, &pkgbuild).is_err());
+    }
+}
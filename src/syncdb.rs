@@ -0,0 +1,215 @@
+use crate::compression::{self, Limits};
+use crate::errors::*;
+use base64::prelude::*;
+use std::collections::HashMap;
+use std::io::Read;
+use std::str;
+
+/// A package listed in a pacman sync database (like `core.db`)
+#[derive(Debug, Default, PartialEq)]
+pub struct SyncDbEntry {
+    pub filename: String,
+    pub name: String,
+    pub base: Option<String>,
+    pub version: String,
+    pub arch: Option<String>,
+    pub csize: Option<u64>,
+    pub sha256sum: Option<String>,
+    pub pgpsig: Option<String>,
+    pub packager: Option<String>,
+}
+
+impl SyncDbEntry {
+    /// Decode the base64 encoded detached signature listed in `%PGPSIG%`
+    pub fn pgpsig(&self) -> Result<Option<Vec<u8>>> {
+        let Some(pgpsig) = &self.pgpsig else {
+            return Ok(None);
+        };
+        let sig = BASE64_STANDARD
+            .decode(pgpsig)
+            .with_context(|| anyhow!("Failed to decode %PGPSIG% of {:?}", self.name))?;
+        Ok(Some(sig))
+    }
+}
+
+/// Parse a `desc` file of a sync database
+pub fn parse_desc(text: &str) -> Result<SyncDbEntry> {
+    let mut fields = HashMap::<&str, Vec<&str>>::new();
+
+    let mut key = None;
+    for line in text.lines() {
+        if line.is_empty() {
+            key = None;
+        } else if let Some(name) = line.strip_prefix('%').and_then(|l| l.strip_suffix('%')) {
+            if fields.contains_key(name) {
+                bail!("Duplicate field in sync database: %{name}%");
+            }
+            fields.insert(name, Vec::new());
+            key = Some(name);
+        } else if let Some(key) = key {
+            fields.entry(key).or_default().push(line);
+        } else {
+            bail!("Unexpected line in sync database: {line:?}");
+        }
+    }
+
+    let single = |name: &str| -> Result<Option<String>> {
+        match fields.get(name).map(|v| v.as_slice()) {
+            None => Ok(None),
+            Some([value]) => Ok(Some(value.to_string())),
+            Some(values) => bail!("Expected exactly one value for %{name}%, found: {values:?}"),
+        }
+    };
+    let required = |name: &str| -> Result<String> {
+        single(name)?.with_context(|| anyhow!("Missing %{name}% in sync database"))
+    };
+
+    let csize = single("CSIZE")?
+        .map(|csize| {
+            csize
+                .parse()
+                .with_context(|| anyhow!("Failed to parse %CSIZE% as number: {csize:?}"))
+        })
+        .transpose()?;
+
+    Ok(SyncDbEntry {
+        filename: required("FILENAME")?,
+        name: required("NAME")?,
+        base: single("BASE")?,
+        version: required("VERSION")?,
+        arch: single("ARCH")?,
+        csize,
+        sha256sum: single("SHA256SUM")?,
+        pgpsig: single("PGPSIG")?,
+        packager: single("PACKAGER")?,
+    })
+}
+
+pub struct SyncDb {
+    pkgs: Vec<SyncDbEntry>,
+}
+
+impl SyncDb {
+    pub fn parse(bytes: &[u8], limits: &Limits) -> Result<Self> {
+        let bytes =
+            compression::decompress(bytes, limits).context("Failed to decompress sync database")?;
+        let mut tar = tar::Archive::new(&bytes[..]);
+
+        let mut pkgs = Vec::new();
+        for (num, entry) in (1..).zip(tar.entries()?) {
+            limits.check_entries(num)?;
+            let mut entry = entry?;
+            let path = entry.path()?;
+            if !path.ends_with("desc") {
+                trace!("Skipping file in sync database: {path:?}");
+                continue;
+            }
+            debug!("Found package in sync database: {path:?}");
+
+            let mut buf = String::new();
+            entry.read_to_string(&mut buf)?;
+            pkgs.push(parse_desc(&buf)?);
+        }
+
+        Ok(SyncDb { pkgs })
+    }
+
+    pub fn pkgs(&self) -> &[SyncDbEntry] {
+        &self.pkgs
+    }
+
+    pub fn find_pkg_by_sha256(&self, sha256: &str, size: u64) -> Result<&SyncDbEntry> {
+        for pkg in &self.pkgs {
+            if pkg.sha256sum.as_deref() != Some(sha256) {
+                continue;
+            }
+
+            if let Some(csize) = pkg.csize {
+                if csize != size {
+                    bail!(
+                        "Package {:?} has matching sha256 but %CSIZE%={csize} does not match size={size}",
+                        pkg.filename
+                    );
+                }
+            }
+
+            return Ok(pkg);
+        }
+
+        bail!("Could not find package with sha256={sha256:?} in sync database")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESC: &str = "%FILENAME%
+cmatrix-2.0-3-x86_64.pkg.tar.zst
+
+%NAME%
+cmatrix
+
+%BASE%
+cmatrix
+
+%VERSION%
+2.0-3
+
+%DESC%
+A curses-based scrolling 'Matrix'-like screen
+
+%CSIZE%
+19871
+
+%ISIZE%
+43573
+
+%SHA256SUM%
+03a7237192794b7789cb40640b151fffa77d832ab0d33bed8778a6d569f0f8ca
+
+%PGPSIG%
+aGVsbG8gd29ybGQ=
+
+%LICENSE%
+GPL3
+
+%ARCH%
+x86_64
+
+%PACKAGER%
+kpcyrd <kpcyrd@archlinux.org>
+
+%DEPENDS%
+ncurses
+
+";
+
+    #[test]
+    fn test_parse_desc() {
+        let entry = parse_desc(DESC).unwrap();
+        assert_eq!(
+            entry,
+            SyncDbEntry {
+                filename: "cmatrix-2.0-3-x86_64.pkg.tar.zst".to_string(),
+                name: "cmatrix".to_string(),
+                base: Some("cmatrix".to_string()),
+                version: "2.0-3".to_string(),
+                arch: Some("x86_64".to_string()),
+                csize: Some(19871),
+                sha256sum: Some(
+                    "03a7237192794b7789cb40640b151fffa77d832ab0d33bed8778a6d569f0f8ca".to_string()
+                ),
+                pgpsig: Some("aGVsbG8gd29ybGQ=".to_string()),
+                packager: Some("kpcyrd <kpcyrd@archlinux.org>".to_string()),
+            }
+        );
+        assert_eq!(entry.pgpsig().unwrap().unwrap(), b"hello world");
+    }
+
+    #[test]
+    fn test_parse_desc_duplicate_field() {
+        let desc = format!("{DESC}%NAME%\nfoo\n");
+        assert!(parse_desc(&desc).is_err());
+    }
+}
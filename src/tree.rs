@@ -0,0 +1,433 @@
+//! Compare the files contained in tarballs, regardless of how the archive itself was created
+use crate::chksums;
+use crate::compression::{self, Limits};
+use crate::errors::*;
+use std::collections::BTreeMap;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub kind: EntryKind,
+    pub mode: u32,
+    pub size: u64,
+    pub sha256: Option<String>,
+    pub link: Option<String>,
+}
+
+/// Strip the `./` prefix and trailing slashes so paths of different tar implementations can be compared
+fn normalize_path(path: &str) -> &str {
+    let path = path.strip_prefix("./").unwrap_or(path);
+    path.trim_end_matches('/')
+}
+
+/// The files of a tarball by path, metadata like timestamps and owners is ignored
+#[derive(Debug, Default, PartialEq)]
+pub struct Tree {
+    pub entries: BTreeMap<String, Entry>,
+}
+
+impl Tree {
+    /// Read an uncompressed tar archive, file contents are hashed without holding them in memory
+    pub fn from_reader<R: Read>(reader: R, limits: &Limits) -> Result<Self> {
+        let mut tar = tar::Archive::new(reader);
+        let mut entries = BTreeMap::<String, Entry>::new();
+        for (num, entry) in (1..).zip(tar.entries()?) {
+            limits.check_entries(num)?;
+            let mut entry = entry?;
+            let path = entry.path()?;
+            let path = path
+                .to_str()
+                .with_context(|| anyhow!("Path in tarball is not valid utf-8: {path:?}"))?;
+            let path = normalize_path(path).to_string();
+
+            let header = entry.header();
+            let mode = header.mode()? & 0o7777;
+            let entry = match header.entry_type() {
+                tar::EntryType::XGlobalHeader => {
+                    // e.g. the commit id written by `git archive`
+                    trace!("Skipping pax global header in tarball");
+                    continue;
+                }
+                kind if kind.is_file() => {
+                    let (sha256, size) = chksums::sha256_reader(&mut entry)?;
+                    Entry {
+                        kind: EntryKind::File,
+                        mode,
+                        size,
+                        sha256: Some(sha256),
+                        link: None,
+                    }
+                }
+                tar::EntryType::Link => {
+                    // hardlinks are compared like the file they point to, tar implementations disagree on when to use them
+                    let target = entry
+                        .link_name()?
+                        .context("Hardlink in tarball has no target")?;
+                    let target = target.to_str().with_context(|| {
+                        anyhow!("Hardlink target is not valid utf-8: {target:?}")
+                    })?;
+                    let target = normalize_path(target);
+                    let target = entries
+                        .get(target)
+                        .filter(|target| target.kind == EntryKind::File)
+                        .with_context(|| {
+                            anyhow!("Hardlink {path:?} points to unknown file: {target:?}")
+                        })?;
+                    Entry {
+                        mode,
+                        ..target.clone()
+                    }
+                }
+                tar::EntryType::Directory => Entry {
+                    kind: EntryKind::Dir,
+                    mode,
+                    size: 0,
+                    sha256: None,
+                    link: None,
+                },
+                tar::EntryType::Symlink => {
+                    let target = entry
+                        .link_name()?
+                        .context("Symlink in tarball has no target")?;
+                    let target = target.to_str().with_context(|| {
+                        anyhow!("Symlink target is not valid utf-8: {target:?}")
+                    })?;
+                    Entry {
+                        kind: EntryKind::Symlink,
+                        // the permissions of symlinks are not used
+                        mode: 0,
+                        size: 0,
+                        sha256: None,
+                        link: Some(target.to_string()),
+                    }
+                }
+                _ => Entry {
+                    kind: EntryKind::Other,
+                    mode,
+                    size: 0,
+                    sha256: None,
+                    link: None,
+                },
+            };
+
+            if entries.insert(path.clone(), entry).is_some() {
+                bail!("Tarball contains duplicate entry: {path:?}");
+            }
+        }
+        Ok(Tree { entries })
+    }
+
+    /// Decompress and read a tarball from disk
+    pub async fn from_path(path: &Path, limits: &Limits) -> Result<Self> {
+        let path = path.to_owned();
+        let limits = *limits;
+        tokio::task::spawn_blocking(move || {
+            let file = std::fs::File::open(&path)
+                .with_context(|| anyhow!("Failed to open file: {path:?}"))?;
+            let reader = compression::reader(BufReader::new(file), &limits)?;
+            Self::from_reader(reader, &limits)
+                .with_context(|| anyhow!("Failed to read tarball: {path:?}"))
+        })
+        .await?
+    }
+
+    /// Decompress and read a tarball from disk, also returns the sha256 and size of the file
+    ///
+    /// The file is only read once, so the hash is guaranteed to belong to the files that were read.
+    pub async fn from_path_hashed(path: &Path, limits: &Limits) -> Result<(Self, (String, u64))> {
+        let path = path.to_owned();
+        let limits = *limits;
+        tokio::task::spawn_blocking(move || {
+            let file = std::fs::File::open(&path)
+                .with_context(|| anyhow!("Failed to open file: {path:?}"))?;
+            let hashing = compression::HashingReader::new(file);
+            let reader = compression::reader(BufReader::new(hashing.clone()), &limits)?;
+            let tree = Self::from_reader(reader, &limits)
+                .with_context(|| anyhow!("Failed to read tarball: {path:?}"))?;
+            let hashed = hashing
+                .finish()
+                .with_context(|| anyhow!("Failed to hash file: {path:?}"))?;
+            Ok((tree, hashed))
+        })
+        .await?
+    }
+
+    /// The top-level directory, if all entries are inside of it
+    pub fn top_level(&self) -> Option<&str> {
+        let mut top_levels = self
+            .entries
+            .keys()
+            .map(|path| path.split_once('/').map_or(path.as_str(), |(top, _)| top));
+        let top_level = top_levels.next()?;
+        if !top_levels.all(|top| top == top_level)
+            || self
+                .entries
+                .get(top_level)
+                .is_some_and(|e| e.kind != EntryKind::Dir)
+        {
+            return None;
+        }
+        Some(top_level)
+    }
+
+    /// Remove the top-level directory if all entries are inside of it
+    ///
+    /// Repacked tarballs often use a different name for it (e.g. `foo-1.0+dfsg.orig/`).
+    pub fn strip_top_level(self) -> Self {
+        let Some(top_level) = self.top_level() else {
+            return self;
+        };
+
+        debug!("Stripping top-level directory {top_level:?} from tarball");
+        let prefix = format!("{top_level}/");
+        let entries = self
+            .entries
+            .into_iter()
+            .filter_map(|(path, entry)| {
+                let path = path.strip_prefix(&prefix)?.to_string();
+                Some((path, entry))
+            })
+            .collect();
+        Tree { entries }
+    }
+}
+
+/// The differences between two trees, by path
+#[derive(Debug, Default, PartialEq)]
+pub struct TreeDiff {
+    /// Paths that only exist in the original tree
+    pub removed: Vec<String>,
+    /// Paths that only exist in the new tree
+    pub added: Vec<String>,
+    /// Paths that exist in both trees, along with the attributes that are different
+    pub altered: BTreeMap<String, Vec<&'static str>>,
+}
+
+impl TreeDiff {
+    pub fn is_identical(&self) -> bool {
+        self.removed.is_empty() && self.added.is_empty() && self.altered.is_empty()
+    }
+
+    /// The new tree only lacks files of the original, everything it contains is unchanged
+    pub fn is_subset(&self) -> bool {
+        self.added.is_empty() && self.altered.is_empty()
+    }
+}
+
+/// Compare entries by path, type, mode, content and link target
+pub fn diff(original: &Tree, new: &Tree) -> TreeDiff {
+    let mut diff = TreeDiff::default();
+    for (path, entry) in &original.entries {
+        let Some(other) = new.entries.get(path) else {
+            diff.removed.push(path.clone());
+            continue;
+        };
+
+        let mut fields = Vec::new();
+        if entry.kind != other.kind {
+            fields.push("type");
+        }
+        if entry.mode != other.mode {
+            fields.push("mode");
+        }
+        if entry.size != other.size || entry.sha256 != other.sha256 {
+            fields.push("content");
+        }
+        if entry.link != other.link {
+            fields.push("link target");
+        }
+        if !fields.is_empty() {
+            diff.altered.insert(path.clone(), fields);
+        }
+    }
+    diff.added = new
+        .entries
+        .keys()
+        .filter(|path| !original.entries.contains_key(*path))
+        .cloned()
+        .collect();
+    diff
+}
+
+/// Problems with files that should have been removed from a repacked tarball
+#[derive(Debug, Default, PartialEq)]
+pub struct Exclusions {
+    /// Paths that are excluded but still present in the new tree
+    pub kept: Vec<String>,
+    /// Paths that have been removed without being excluded
+    pub unexpected: Vec<String>,
+}
+
+impl Exclusions {
+    pub fn is_empty(&self) -> bool {
+        self.kept.is_empty() && self.unexpected.is_empty()
+    }
+}
+
+/// Check the new tree lacks exactly the excluded paths of the original
+///
+/// Removed directories are not reported, they have no content and repacking tools may drop them once empty.
+pub fn check_exclusions<F: Fn(&str) -> bool>(
+    original: &Tree,
+    new: &Tree,
+    is_excluded: F,
+) -> Exclusions {
+    let mut exclusions = Exclusions::default();
+    for (path, entry) in &original.entries {
+        match (new.entries.contains_key(path), is_excluded(path)) {
+            (true, true) => exclusions.kept.push(path.clone()),
+            (false, false) if entry.kind != EntryKind::Dir => {
+                exclusions.unexpected.push(path.clone())
+            }
+            _ => (),
+        }
+    }
+    exclusions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tarball(prefix: &str, files: &[(&str, u32, &[u8])], mtime: u64) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        if !prefix.is_empty() {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            header.set_mtime(mtime);
+            builder.append_data(&mut header, prefix, &[][..]).unwrap();
+        }
+
+        for (path, mode, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(*mode);
+            header.set_size(content.len() as u64);
+            header.set_mtime(mtime);
+            builder
+                .append_data(&mut header, format!("{prefix}{path}"), *content)
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn tree(bytes: &[u8]) -> Tree {
+        Tree::from_reader(bytes, &Limits::DEFAULT)
+            .unwrap()
+            .strip_top_level()
+    }
+
+    const FILES: &[(&str, u32, &[u8])] = &[
+        ("README", 0o644, b"hello world\n"),
+        ("configure", 0o755, b"#!/bin/sh\n"),
+        ("blob.bin", 0o644, b"\x7fELF"),
+    ];
+
+    #[test]
+    fn test_identical_trees() {
+        let upstream = tree(&tarball("foo-1.0/", FILES, 1));
+        let orig = tree(&tarball("./foo-1.0+dfsg.orig/", FILES, 1700000000));
+        assert_eq!(upstream.entries.len(), 3);
+        let diff = diff(&upstream, &orig);
+        assert!(diff.is_identical());
+    }
+
+    #[test]
+    fn test_top_level() {
+        let bytes = tarball("foo-1.0/", FILES, 1);
+        let tree = Tree::from_reader(&bytes[..], &Limits::DEFAULT).unwrap();
+        assert_eq!(tree.top_level(), Some("foo-1.0"));
+        assert_eq!(tree.strip_top_level().top_level(), None);
+    }
+
+    #[test]
+    fn test_removed_files() {
+        let upstream = tree(&tarball("foo-1.0/", FILES, 1));
+        let orig = tree(&tarball("foo-1.0/", &FILES[..2], 1));
+        let diff = diff(&upstream, &orig);
+        assert!(!diff.is_identical());
+        assert!(diff.is_subset());
+        assert_eq!(diff.removed, &["blob.bin"]);
+    }
+
+    #[test]
+    fn test_altered_files() {
+        let upstream = tree(&tarball("foo-1.0/", FILES, 1));
+        let orig = tree(&tarball(
+            "foo-1.0/",
+            &[
+                ("README", 0o644, b"hello world!\n"),
+                ("configure", 0o644, b"#!/bin/sh\n"),
+                ("blob.bin", 0o644, b"\x7fELF"),
+                ("debian/rules", 0o755, b""),
+            ],
+            1,
+        ));
+        let diff = diff(&upstream, &orig);
+        assert!(!diff.is_subset());
+        assert_eq!(diff.added, &["debian/rules"]);
+        assert_eq!(
+            diff.altered,
+            BTreeMap::from([
+                ("README".to_string(), vec!["content"]),
+                ("configure".to_string(), vec!["mode"]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_hardlink_as_file() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(12);
+        builder
+            .append_data(&mut header, "README", &b"hello world\n"[..])
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Link);
+        header.set_mode(0o644);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "README.md", "README")
+            .unwrap();
+        let linked = tree(&builder.into_inner().unwrap());
+
+        let copied = tree(&tarball(
+            "",
+            &[
+                ("README", 0o644, b"hello world\n"),
+                ("README.md", 0o644, b"hello world\n"),
+            ],
+            1,
+        ));
+        assert!(diff(&linked, &copied).is_identical());
+    }
+
+    #[test]
+    fn test_check_exclusions() {
+        let upstream = tree(&tarball("foo-1.0/", FILES, 1));
+        let orig = tree(&tarball("foo-1.0+dfsg/", &FILES[..2], 1));
+        let exclusions = check_exclusions(&upstream, &orig, |path| path.ends_with(".bin"));
+        assert!(exclusions.is_empty());
+
+        let exclusions = check_exclusions(&upstream, &orig, |path| path == "README");
+        assert_eq!(
+            exclusions,
+            Exclusions {
+                kept: vec!["README".to_string()],
+                unexpected: vec!["blob.bin".to_string()],
+            }
+        );
+    }
+}
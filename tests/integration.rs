@@ -1,7 +1,9 @@
 use backseat_signed::apt;
 use backseat_signed::buildinfo;
 use backseat_signed::chksums;
+use backseat_signed::compression::Limits;
 use backseat_signed::errors::*;
+use backseat_signed::mtree;
 use backseat_signed::pgp;
 use backseat_signed::pkgbuild;
 use std::io::Read;
@@ -50,7 +52,9 @@ fn parse_lookup_apt_release_xz() {
     .unwrap();
     let sha256 = chksums::sha256(&sources);
 
-    let _sources_entry = release.find_source_entry_by_sha256(&sha256).unwrap();
+    let _sources_entry = release
+        .find_source_entry("main/source/Sources.xz", &sha256, sources.len() as u64)
+        .unwrap();
 }
 
 #[test]
@@ -69,7 +73,9 @@ fn parse_lookup_apt_release_gz() {
     .unwrap();
     let sha256 = chksums::sha256(&sources);
 
-    let _sources_entry = release.find_source_entry_by_sha256(&sha256).unwrap();
+    let _sources_entry = release
+        .find_source_entry("main/source/Sources.gz", &sha256, sources.len() as u64)
+        .unwrap();
 }
 
 #[test]
@@ -91,7 +97,63 @@ fn parse_lookup_apt_release_plain() {
     .unwrap();
     let sha256 = chksums::sha256(&sources);
 
-    let _sources_entry = release.find_source_entry_by_sha256(&sha256).unwrap();
+    let _sources_entry = release
+        .find_source_entry("main/source/Sources", &sha256, sources.len() as u64)
+        .unwrap();
+}
+
+#[test]
+fn parse_lookup_apt_release_any_variant() {
+    let release = git_integration_data(
+        "data/vim/Release",
+        "0bba2751e8ab74cf19c628db12e921d8753be857c77a54652fb0a25767bef92a",
+    )
+    .unwrap();
+    let release = apt::Release::parse(&release).unwrap();
+
+    let sources = git_integration_data(
+        "data/vim/Sources.xz",
+        "ba14ca35563ace9dc1e81446f6d72979cdc5aa7ea5c558cb0fe5071736c602b2",
+    )
+    .unwrap();
+    let entry = release
+        .find_sources_entry("main/source/Sources", &sources, &Limits::DEFAULT)
+        .unwrap();
+    assert_eq!(entry.filename, "main/source/Sources.xz");
+
+    let sources = git_integration_data(
+        "data/vim/Sources.gz",
+        "4fff7a4e41c2f22240d3905de0eb85640082b74f544d66cfafef277d6c5a8b14",
+    )
+    .unwrap();
+    let entry = release
+        .find_sources_entry("main/source/Sources.xz", &sources, &Limits::DEFAULT)
+        .unwrap();
+    assert_eq!(entry.filename, "main/source/Sources.gz");
+}
+
+#[test]
+fn parse_lookup_apt_release_by_hash() {
+    let release = git_integration_data(
+        "data/vim/Release",
+        "0bba2751e8ab74cf19c628db12e921d8753be857c77a54652fb0a25767bef92a",
+    )
+    .unwrap();
+    let release = apt::Release::parse(&release).unwrap();
+
+    let sources = git_integration_data(
+        "data/vim/Sources.xz",
+        "ba14ca35563ace9dc1e81446f6d72979cdc5aa7ea5c558cb0fe5071736c602b2",
+    )
+    .unwrap();
+    let entry = release
+        .find_sources_entry(
+            "main/source/by-hash/SHA256/ba14ca35563ace9dc1e81446f6d72979cdc5aa7ea5c558cb0fe5071736c602b2",
+            &sources,
+            &Limits::DEFAULT,
+        )
+        .unwrap();
+    assert_eq!(entry.filename, "main/source/Sources.xz");
 }
 
 #[test]
@@ -104,7 +166,7 @@ fn parse_lookup_apt_sources_vim() {
         .unwrap(),
     )
     .unwrap();
-    let sources = apt::SourcesIndex::parse(&sources).unwrap();
+    let sources = apt::SourcesIndex::parse(&sources, &Limits::DEFAULT).unwrap();
 
     let content = git_integration_data(
         "data/vim/vim_9.1.0199.orig.tar.xz",
@@ -164,6 +226,41 @@ fn test_archlinux_verify_pkg_sig() {
     pgp::verify(&keyring, &sig, &pkg).unwrap();
 }
 
+#[test]
+fn test_pgp_verify_filtered_keyring() {
+    let keyring = git_integration_data(
+        "data/kpcyrd.asc",
+        "4cc0ddd01c958b6a9fc0eb689b581f088d9c5a74b7ac5ba72594c1c85c09ce32",
+    )
+    .unwrap();
+
+    let sig = git_integration_data(
+        "data/cmatrix/cmatrix-2.0-3-x86_64.pkg.tar.zst.sig",
+        "ce8fe71e99503512f09beddcfd4ae8961037f6ada0bed2f731080c02806dc8ed",
+    )
+    .unwrap();
+    let sig = pgp::signature(&sig).unwrap();
+
+    let pkg = git_integration_data(
+        "data/cmatrix/cmatrix-2.0-3-x86_64.pkg.tar.zst",
+        "03a7237192794b7789cb40640b151fffa77d832ab0d33bed8778a6d569f0f8ca",
+    )
+    .unwrap();
+
+    let allowed = pgp::filter_keyring(
+        pgp::keyring(&keyring).unwrap(),
+        &["64B13F7117D6E07D661BBCE0FE763A64F5E54FD6".to_string()],
+    )
+    .unwrap();
+    pgp::verify(&allowed, &sig, &pkg).unwrap();
+
+    let unrelated = pgp::filter_keyring(
+        pgp::keyring(&keyring).unwrap(),
+        &["0000000000000000000000000000000000000000".to_string()],
+    );
+    assert!(unrelated.is_err());
+}
+
 #[test]
 fn test_archlinux_pkgbuild_hash() {
     let pkg = git_integration_data(
@@ -172,7 +269,10 @@ fn test_archlinux_pkgbuild_hash() {
     )
     .unwrap();
 
-    let buildinfo = buildinfo::from_archlinux_pkg(&pkg).unwrap();
+    let buildinfo = buildinfo::from_archlinux_pkg(&pkg, &Limits::DEFAULT).unwrap();
+    assert_eq!(buildinfo.pkgname, "cmatrix");
+    assert_eq!(buildinfo.pkgver, "2.0-3");
+    assert_eq!(buildinfo.pkgarch, "x86_64");
     let pkgbuild_sha256sum = buildinfo.pkgbuild_sha256sum;
 
     assert_eq!(
@@ -181,6 +281,32 @@ fn test_archlinux_pkgbuild_hash() {
     );
 }
 
+#[test]
+fn test_archlinux_pkg_metadata() {
+    let pkg = git_integration_data(
+        "data/cmatrix/cmatrix-2.0-3-x86_64.pkg.tar.zst",
+        "03a7237192794b7789cb40640b151fffa77d832ab0d33bed8778a6d569f0f8ca",
+    )
+    .unwrap();
+
+    let metadata = buildinfo::metadata_from_archlinux_pkg(&pkg, &Limits::DEFAULT).unwrap();
+    assert_eq!(metadata.pkginfo.pkgname, "cmatrix");
+    assert_eq!(metadata.pkginfo.pkgver, "2.0-3");
+    metadata.check_consistency().unwrap();
+}
+
+#[test]
+fn test_archlinux_pkg_mtree() {
+    let pkg = git_integration_data(
+        "data/cmatrix/cmatrix-2.0-3-x86_64.pkg.tar.zst",
+        "03a7237192794b7789cb40640b151fffa77d832ab0d33bed8778a6d569f0f8ca",
+    )
+    .unwrap();
+
+    let num = mtree::verify_archlinux_pkg(&pkg, &Limits::DEFAULT).unwrap();
+    assert!(num > 0);
+}
+
 #[test]
 fn test_archlinux_pkgbuild_artifact() {
     let pkgbuild = git_integration_data(
@@ -189,11 +315,15 @@ fn test_archlinux_pkgbuild_artifact() {
     )
     .unwrap();
     let pkgbuild = pkgbuild::parse(&pkgbuild).unwrap();
+    pkgbuild.check_package("cmatrix", "cmatrix").unwrap();
 
     let pkg = git_integration_data(
         "data/cmatrix/cmatrix-2.0.tar.gz",
         "ad93ba39acd383696ab6a9ebbed1259ecf2d3cf9f49d6b97038c66f80749e99a",
     )
     .unwrap();
-    pkgbuild.has_artifact_by_checksum(&pkg).unwrap();
+    let m = pkgbuild
+        .has_artifact_by_checksum(&pkg, Some("x86_64"))
+        .unwrap();
+    assert!(m.source.is_some());
 }